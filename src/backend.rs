@@ -1,11 +1,164 @@
-use std::{cell::RefCell, collections::HashMap, hash::Hash, rc::Rc};
+use std::{collections::HashMap, fmt, rc::Rc, str::FromStr};
 
 use crate::{
-    parser::{Data, Parser, Stmt},
+    diagnostic::Diagnostic,
+    lint,
+    object::{ISA_VERSION, Object, Symbol, SymbolKind},
+    parser::{Address, Data, ParseError, Parser, Reserve, Stmt},
     scanner::{Token, TokenType},
 };
 const I24_MIN: i32 = -8_388_608;
 const I24_MAX: i32 = 8_388_607;
+/// Execution starts at this label when the program defines it.
+pub const ENTRY_LABEL: &str = "_start";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CodegenError {
+    ExpectedRegister { found: TokenType, line: usize },
+    UndefinedLabel { name: String, line: usize },
+    UndefinedSymbol { name: String, line: usize },
+    ImmediateOutOfRange { literal: String, line: usize },
+    DataTooLarge { name: String, line: usize },
+    BranchOutOfRange { name: String, line: usize },
+}
+
+impl fmt::Display for CodegenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CodegenError::ExpectedRegister { found, line } => {
+                write!(f, "expected a register, found {found:?} at line {line}")
+            }
+            CodegenError::UndefinedLabel { name, line } => {
+                write!(f, "undefined label `{name}` at line {line}")
+            }
+            CodegenError::UndefinedSymbol { name, line } => {
+                write!(f, "undefined data symbol `{name}` at line {line}")
+            }
+            CodegenError::ImmediateOutOfRange { literal, line } => {
+                write!(f, "immediate {literal} out of range at line {line}")
+            }
+            CodegenError::DataTooLarge { name, line } => {
+                write!(f, "data section is full at `{name}` on line {line}")
+            }
+            CodegenError::BranchOutOfRange { name, line } => {
+                write!(f, "label `{name}` is too far to reach at line {line}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for CodegenError {}
+
+fn register(token: &Token) -> Result<u8, CodegenError> {
+    token
+        .token_type
+        .get_reg()
+        .map(|reg| reg.0 as u8)
+        .ok_or(CodegenError::ExpectedRegister {
+            found: token.token_type,
+            line: token.line_number,
+        })
+}
+
+fn immediate<T: FromStr>(token: &Token) -> Result<T, CodegenError> {
+    let literal = token.literal.clone().unwrap_or_default();
+    literal
+        .parse::<T>()
+        .map_err(|_| CodegenError::ImmediateOutOfRange {
+            literal,
+            line: token.line_number,
+        })
+}
+
+/// Whether `token` is an immediate that needs an extension word.
+fn is_wide(token: &Token) -> bool {
+    token.token_type == TokenType::INT && immediate::<i16>(token).is_err()
+}
+
+/// Encoded size of a statement in bytes, 8 when it carries an extension
+/// word.
+fn size(stmt: &Stmt) -> u32 {
+    match stmt {
+        Stmt::MovLit {
+            register_or_imm_ident: imm,
+            ..
+        }
+        | Stmt::ADD {
+            right_reg_imm: imm, ..
+        }
+        | Stmt::SUB {
+            right_reg_imm: imm, ..
+        }
+        | Stmt::MUL {
+            right_reg_imm: imm, ..
+        }
+        | Stmt::DIV {
+            right_reg_imm: imm, ..
+        }
+        | Stmt::MOD {
+            right_reg_imm: imm, ..
+        }
+        | Stmt::CMP {
+            register_or_imm: imm,
+            ..
+        }
+        | Stmt::AndOrXor {
+            register_or_imm: imm,
+            ..
+        }
+        | Stmt::Shift {
+            register_or_imm: imm,
+            ..
+        }
+        | Stmt::Test {
+            register_or_imm: imm,
+            ..
+        }
+        | Stmt::Alloc {
+            register_or_imm: imm,
+            ..
+        } if is_wide(imm) => 8,
+        Stmt::PUSH {
+            register_or_imm: imm,
+        } if imm.token_type == TokenType::INT
+            && immediate::<i32>(imm).is_ok_and(|imm| !(I24_MIN..=I24_MAX).contains(&imm)) =>
+        {
+            8
+        }
+        _ => 4,
+    }
+}
+
+/// Position of a load/store mnemonic in its byte, word, dword opcode group.
+fn width_index(type_op: TokenType) -> u8 {
+    match type_op {
+        TokenType::LOADB | TokenType::STOREB => 0,
+        TokenType::LOADW | TokenType::STOREW => 1,
+        _ => 2,
+    }
+}
+
+/// Where a `.data` declaration or `.bss` reservation ended up.
+#[derive(Debug, Clone, Copy)]
+struct DataSymbol {
+    kind: SymbolKind,
+    offset: u16,
+    /// Element size in bytes.
+    width: u8,
+    /// Length of the whole declaration in bytes.
+    len: u16,
+}
+
+impl DataSymbol {
+    /// Bit 6 of the register byte selects the bss segment for symbolic
+    /// operands, the offset is relative to the start of that segment.
+    fn segment(&self) -> u8 {
+        match self.kind {
+            SymbolKind::Bss => 0x40,
+            _ => 0x00,
+        }
+    }
+}
 
 pub struct CodeGen {
     code: Vec<u8>,
@@ -13,206 +166,320 @@ pub struct CodeGen {
     statements: Rc<Vec<Stmt>>,
     data: Rc<Vec<Data>>,
     table: HashMap<String, usize>,
-    data_tabel: HashMap<String, u16>,
+    data_tabel: HashMap<String, DataSymbol>,
+    bss: Rc<Vec<Reserve>>,
+    bss_size: u32,
+    /// Byte address of every statement, plus the end of the code, so a label
+    /// after the last instruction still resolves.
+    addresses: Vec<u32>,
 }
 impl CodeGen {
-    pub fn new(mut parser: Parser) -> Self {
-        let code = parser.parse();
+    pub fn new(mut parser: Parser) -> Result<Self, Vec<ParseError>> {
+        let code = parser.parse()?;
 
-        Self {
+        Ok(Self {
             code: vec![],
             statements: Rc::new(code.0.to_vec()),
             data: Rc::new(code.1.to_vec()),
             data_code: vec![],
             data_tabel: HashMap::new(),
             table: parser.get_table().clone(),
-        }
+            bss: Rc::new(parser.get_bss().to_vec()),
+            bss_size: 0,
+            addresses: vec![],
+        })
     }
-    pub fn helper_reg(&mut self, op1: u8, op2: u8, lhs_reg: &Token, right_reg_imm: &Token) {
-        let reg = lhs_reg.token_type.get_reg();
+    pub fn helper_reg(
+        &mut self,
+        op1: u8,
+        op2: u8,
+        lhs_reg: &Token,
+        right_reg_imm: &Token,
+    ) -> Result<(), CodegenError> {
+        let reg = register(lhs_reg)?;
         let mut command: [u8; 4] = [0; 4];
         if right_reg_imm.token_type != TokenType::INT {
-            let reg_2 = right_reg_imm.token_type.get_reg();
+            let reg_2 = register(right_reg_imm)?;
             command[3] = op1;
-            command[2] = reg.0 as u8;
-            command[1] = reg_2.0 as u8;
-        } else {
-            let reg_2 = right_reg_imm
-                .literal
-                .as_ref()
-                .unwrap()
-                .parse::<i16>()
-                .expect("parsing error");
-
+            command[2] = reg;
+            command[1] = reg_2;
+        } else if let Ok(reg_2) = immediate::<i16>(right_reg_imm) {
             let [high, low] = reg_2.to_be_bytes();
 
             command[3] = op2;
-            command[2] = reg.0 as u8;
+            command[2] = reg;
             command[1] = high;
             command[0] = low;
+        } else {
+            // bit 7 of the register byte: the immediate is the next word
+            let reg_2 = immediate::<i32>(right_reg_imm)?;
+            command[3] = op2;
+            command[2] = reg | 0x80;
+            self.code.extend_from_slice(&command);
+            self.code.extend_from_slice(&reg_2.to_le_bytes());
+            return Ok(());
         }
         self.code.extend_from_slice(&command);
+        Ok(())
+    }
+    /// Emits a jump or call. The operand is the signed 24-bit distance in
+    /// bytes from this instruction to the label.
+    pub fn helper_jump(&mut self, op: u8, to: &Token) -> Result<(), CodegenError> {
+        let target = self.label(to)?;
+        let offset = target as i64 - self.code.len() as i64;
+        let offset = i32::try_from(offset)
+            .ok()
+            .filter(|offset| (I24_MIN..=I24_MAX).contains(offset))
+            .ok_or(CodegenError::BranchOutOfRange {
+                name: to.literal.clone().unwrap_or_default(),
+                line: to.line_number,
+            })?;
+        let [_, u2, u3, u4] = offset.to_be_bytes();
+        self.code.extend_from_slice(&[u4, u3, u2, op]);
+        Ok(())
+    }
+    /// Emits `mov`/`cmp` with a data symbol operand. The symbol's width is
+    /// encoded in bits 4-5 of the register byte: 0 byte, 1 word, 2 dword.
+    /// Bit 6 selects the segment, see [`DataSymbol::segment`].
+    fn helper_data(&mut self, op: u8, reg: &Token, ident: &Token) -> Result<(), CodegenError> {
+        let reg = register(reg)?;
+        let symbol = self.data_symbol(ident)?;
+        let [high, low] = symbol.offset.to_be_bytes();
+        let width = symbol.width.trailing_zeros() as u8;
+        self.code
+            .extend_from_slice(&[low, high, symbol.segment() | width << 4 | reg, op]);
+        Ok(())
+    }
+    /// Emits a memory instruction, `op_reg` for `[rN + imm]` and `op_label`
+    /// for `[label]`. `reg` is the register operand, 0 for instructions
+    /// without one.
+    fn helper_mem(
+        &mut self,
+        op_reg: u8,
+        op_label: u8,
+        reg: u8,
+        address: &Address,
+    ) -> Result<(), CodegenError> {
+        let command = match address {
+            Address::Register { base, offset } => {
+                let base_reg = register(base)?;
+                let offset =
+                    i16::try_from(*offset).map_err(|_| CodegenError::ImmediateOutOfRange {
+                        literal: offset.to_string(),
+                        line: base.line_number,
+                    })?;
+                let [high, low] = offset.to_be_bytes();
+                [low, high, reg << 4 | base_reg, op_reg]
+            }
+            Address::Label(ident) => {
+                let symbol = self.data_symbol(ident)?;
+                let [high, low] = symbol.offset.to_be_bytes();
+                [low, high, symbol.segment() | reg, op_label]
+            }
+        };
+        self.code.extend_from_slice(&command);
+        Ok(())
+    }
+    /// Emits `lea rN, [label]` for a code label, e.g. to fill in a trap
+    /// vector. Like jumps, the operand is relative to this instruction.
+    fn helper_code_address(&mut self, reg: &Token, ident: &Token) -> Result<(), CodegenError> {
+        let reg = register(reg)?;
+        let offset = self.label(ident)? as i64 - self.code.len() as i64;
+        let offset = i16::try_from(offset).map_err(|_| CodegenError::BranchOutOfRange {
+            name: ident.literal.clone().unwrap_or_default(),
+            line: ident.line_number,
+        })?;
+        let [high, low] = offset.to_be_bytes();
+        self.code.extend_from_slice(&[low, high, reg, 0x6C]);
+        Ok(())
+    }
+    /// Whether `ident` names a code label rather than a data symbol.
+    fn is_code_label(&self, ident: &Token) -> bool {
+        let name = ident.literal.clone().unwrap_or_default();
+        !self.data_tabel.contains_key(&name) && self.table.contains_key(&name)
+    }
+    fn label(&self, to: &Token) -> Result<u32, CodegenError> {
+        let name = to.literal.clone().unwrap_or_default();
+        match self.table.get(&name) {
+            Some(index) => Ok(self.addresses[*index]),
+            None => Err(CodegenError::UndefinedLabel {
+                name,
+                line: to.line_number,
+            }),
+        }
+    }
+    fn data_symbol(&self, ident: &Token) -> Result<DataSymbol, CodegenError> {
+        let name = ident.literal.clone().unwrap_or_default();
+        match self.data_tabel.get(&name) {
+            Some(symbol) => Ok(*symbol),
+            None => Err(CodegenError::UndefinedSymbol {
+                name,
+                line: ident.line_number,
+            }),
+        }
     }
-    pub fn gen_(&mut self) -> (&[u8], &[u8]) {
-        let mut offset = 0;
+    /// Emits the code and data from scratch, so generating twice gives the
+    /// same output.
+    fn gen_(&mut self) -> Result<(&[u8], &[u8]), CodegenError> {
+        self.code.clear();
+        self.data_code.clear();
+        self.data_tabel.clear();
         for i in self.data.clone().iter() {
-            match i {
-                Data::DB(a, b) => {
-                    let name = b.literal.as_ref().unwrap();
-                    self.data_code.push(*a as u8);
-                    self.data_tabel.insert(name.clone(), offset);
-                    offset += 1;
-                }
-                _ => panic!("not defined"),
+            let (width, bytes, name): (usize, Vec<u8>, _) = match i {
+                Data::DB(values, name) => (1, values.iter().map(|v| *v as u8).collect(), name),
+                Data::DW(values, name) => (
+                    2,
+                    values.iter().flat_map(|v| v.to_le_bytes()).collect(),
+                    name,
+                ),
+                Data::DD(values, name) => (
+                    4,
+                    values.iter().flat_map(|v| v.to_le_bytes()).collect(),
+                    name,
+                ),
+            };
+            // words and dwords are aligned to their own size
+            while !self.data_code.len().is_multiple_of(width) {
+                self.data_code.push(0);
             }
+            let too_large = || CodegenError::DataTooLarge {
+                name: name.literal.clone().unwrap_or_default(),
+                line: name.line_number,
+            };
+            let offset = u16::try_from(self.data_code.len()).map_err(|_| too_large())?;
+            let len = u16::try_from(bytes.len()).map_err(|_| too_large())?;
+            self.data_tabel.insert(
+                name.literal.clone().unwrap_or_default(),
+                DataSymbol {
+                    kind: SymbolKind::Data,
+                    offset,
+                    width: width as u8,
+                    len,
+                },
+            );
+            self.data_code.extend_from_slice(&bytes);
         }
+        // the bss only takes up space once loaded, just hand out offsets
+        let mut offset = 0u32;
+        for Reserve { width, count, name } in self.bss.clone().iter() {
+            offset = offset.next_multiple_of(*width as u32);
+            let len = *width as u32 * *count as u32;
+            let too_large = || CodegenError::DataTooLarge {
+                name: name.literal.clone().unwrap_or_default(),
+                line: name.line_number,
+            };
+            self.data_tabel.insert(
+                name.literal.clone().unwrap_or_default(),
+                DataSymbol {
+                    kind: SymbolKind::Bss,
+                    offset: u16::try_from(offset).map_err(|_| too_large())?,
+                    width: *width,
+                    len: u16::try_from(len).map_err(|_| too_large())?,
+                },
+            );
+            offset += len;
+        }
+        self.bss_size = offset;
+        // first pass: lay out the code so forward references resolve
+        let mut address = 0;
+        self.addresses = self
+            .statements
+            .iter()
+            .map(|stmt| {
+                let at = address;
+                address += size(stmt);
+                at
+            })
+            .collect();
+        self.addresses.push(address);
+        // second pass: emit, every label now has an address
         for i in self.statements.clone().iter() {
             match i {
                 Stmt::MOD {
                     lhs_reg,
                     right_reg_imm,
-                } => {
-                    let reg = lhs_reg.token_type.get_reg();
-                    let mut command: [u8; 4] = [0; 4];
-                    if right_reg_imm.token_type != TokenType::INT {
-                        let reg_2 = right_reg_imm.token_type.get_reg();
-                        command[3] = 0x33;
-                        command[2] = reg.0 as u8;
-                        command[1] = reg_2.0 as u8;
-                    } else {
-                        let reg_2 = right_reg_imm
-                            .literal
-                            .as_ref()
-                            .unwrap()
-                            .parse::<i16>()
-                            .expect("parsing error");
-
-                        let [high, low] = reg_2.to_be_bytes();
-
-                        command[3] = 0x34;
-                        command[2] = reg.0 as u8;
-                        command[1] = high;
-                        command[0] = low;
-                    }
-                    self.code.extend_from_slice(&command);
-                }
+                } => self.helper_reg(0x33, 0x34, lhs_reg, right_reg_imm)?,
                 Stmt::DIV {
                     lhs_reg,
                     right_reg_imm,
-                } => {
-                    let reg = lhs_reg.token_type.get_reg();
-                    let mut command: [u8; 4] = [0; 4];
-                    if right_reg_imm.token_type != TokenType::INT {
-                        let reg_2 = right_reg_imm.token_type.get_reg();
-                        command[3] = 0x13;
-                        command[2] = reg.0 as u8;
-                        command[1] = reg_2.0 as u8;
-                    } else {
-                        let reg_2 = right_reg_imm
-                            .literal
-                            .as_ref()
-                            .unwrap()
-                            .parse::<i16>()
-                            .expect("parsing error");
-
-                        let [high, low] = reg_2.to_be_bytes();
-
-                        command[3] = 0x32;
-                        command[2] = reg.0 as u8;
-                        command[1] = high;
-                        command[0] = low;
-                    }
-                    self.code.extend_from_slice(&command);
-                }
+                } => self.helper_reg(0x13, 0x32, lhs_reg, right_reg_imm)?,
                 Stmt::MUL {
                     lhs_reg,
                     right_reg_imm,
-                } => {
-                    let reg = lhs_reg.token_type.get_reg();
-                    let mut command: [u8; 4] = [0; 4];
-                    if right_reg_imm.token_type != TokenType::INT {
-                        let reg_2 = right_reg_imm.token_type.get_reg();
-                        command[3] = 0x12;
-                        command[2] = reg.0 as u8;
-                        command[1] = reg_2.0 as u8;
-                    } else {
-                        let reg_2 = right_reg_imm
-                            .literal
-                            .as_ref()
-                            .unwrap()
-                            .parse::<i16>()
-                            .expect("parsing error");
-
-                        let [high, low] = reg_2.to_be_bytes();
-
-                        command[3] = 0x31;
-                        command[2] = reg.0 as u8;
-                        command[1] = high;
-                        command[0] = low;
-                    }
-                    self.code.extend_from_slice(&command);
-                }
-                Stmt::AND_OR_XOR {
+                } => self.helper_reg(0x31, 0x12, lhs_reg, right_reg_imm)?,
+                Stmt::AndOrXor {
                     type_op,
                     reg,
                     register_or_imm,
                 } => {
                     if *type_op == TokenType::AND {
-                        self.helper_reg(0x24, 0x25, reg, register_or_imm);
+                        self.helper_reg(0x24, 0x25, reg, register_or_imm)?;
                     } else if *type_op == TokenType::OR {
-                        self.helper_reg(0x26, 0x27, reg, register_or_imm);
+                        self.helper_reg(0x26, 0x27, reg, register_or_imm)?;
                     } else if *type_op == TokenType::XOR {
-                        self.helper_reg(0x28, 0x29, reg, register_or_imm);
+                        self.helper_reg(0x28, 0x29, reg, register_or_imm)?;
                     }
                 }
-                Stmt::JMPLE { to } => {
-                    let val = to.literal.as_ref().unwrap();
-                    let val = (*self.table.get(val).unwrap()) as u32;
-                    let mut command: [u8; 4] = [0; 4];
-                    let [_, u2, u3, u4] = val.to_be_bytes();
-                    command[3] = 0x10;
-                    command[2] = u2;
-                    command[1] = u3;
-                    command[0] = u4;
-                    self.code.extend_from_slice(&command);
+                Stmt::Not { reg } => {
+                    let reg = register(reg)?;
+                    self.code.extend_from_slice(&[0x00, 0x00, reg, 0x30]);
                 }
-                Stmt::JMPGE { to } => {
-                    let val = to.literal.as_ref().unwrap();
-                    let val = (*self.table.get(val).unwrap()) as u32;
-                    let mut command: [u8; 4] = [0; 4];
-                    let [_, u2, u3, u4] = val.to_be_bytes();
-                    command[3] = 0x09;
-                    command[2] = u2;
-                    command[1] = u3;
-                    command[0] = u4;
-                    self.code.extend_from_slice(&command);
+                Stmt::IncDecNeg { type_op, reg } => {
+                    let reg = register(reg)?;
+                    let op = match type_op {
+                        TokenType::INC => 0x59,
+                        TokenType::DEC => 0x5A,
+                        _ => 0x5B,
+                    };
+                    self.code.extend_from_slice(&[0x00, 0x00, reg, op]);
                 }
-                Stmt::JMPZ { to } => {
-                    let val = to.literal.as_ref().unwrap();
-                    let val = (*self.table.get(val).unwrap()) as u32;
-                    let mut command: [u8; 4] = [0; 4];
-                    let [_, u2, u3, u4] = val.to_be_bytes();
-                    command[3] = 0x14;
-                    command[2] = u2;
-                    command[1] = u3;
-                    command[0] = u4;
-                    self.code.extend_from_slice(&command);
+                Stmt::Test {
+                    reg,
+                    register_or_imm,
+                } => self.helper_reg(0x5C, 0x5D, reg, register_or_imm)?,
+                Stmt::Shift {
+                    type_op,
+                    reg,
+                    register_or_imm,
+                } => {
+                    // register form, then immediate form, for each kind
+                    let op = match type_op {
+                        TokenType::SHL => 0x4F,
+                        TokenType::SHR => 0x51,
+                        TokenType::SAR => 0x53,
+                        TokenType::ROL => 0x55,
+                        _ => 0x57,
+                    };
+                    self.helper_reg(op, op + 1, reg, register_or_imm)?;
                 }
+                Stmt::JMPLE { to } => self.helper_jump(0x10, to)?,
+                Stmt::JMPGE { to } => self.helper_jump(0x09, to)?,
+                Stmt::JMPZ { to } => self.helper_jump(0x14, to)?,
+                Stmt::JMPNZ { to } => self.helper_jump(0x15, to)?,
+                Stmt::JC { to } => self.helper_jump(0x45, to)?,
+                Stmt::JNC { to } => self.helper_jump(0x46, to)?,
+                Stmt::JO { to } => self.helper_jump(0x47, to)?,
+                Stmt::JA { to } => self.helper_jump(0x48, to)?,
+                Stmt::JB { to } => self.helper_jump(0x49, to)?,
+                Stmt::JAE { to } => self.helper_jump(0x4A, to)?,
+                Stmt::JBE { to } => self.helper_jump(0x4B, to)?,
+                Stmt::JE { to } => self.helper_jump(0x4C, to)?,
+                Stmt::JNE { to } => self.helper_jump(0x4D, to)?,
+                Stmt::JEOF { to } => self.helper_jump(0x68, to)?,
                 Stmt::PUSH { register_or_imm } => {
                     let mut command: [u8; 4] = [0; 4];
                     if register_or_imm.token_type != TokenType::INT {
-                        let reg_2 = register_or_imm.token_type.get_reg();
+                        let reg_2 = register(register_or_imm)?;
                         command[3] = 0x23;
-                        command[2] = reg_2.0 as u8;
+                        command[2] = reg_2;
                     } else {
-                        let reg_2 = register_or_imm
-                            .literal
-                            .as_ref()
-                            .unwrap()
-                            .parse::<i32>()
-                            .expect("parsing error");
+                        let reg_2 = immediate::<i32>(register_or_imm)?;
                         if !(I24_MIN..=I24_MAX).contains(&reg_2) {
-                            panic!("size overflow");
+                            // push imm32, the value is the next word
+                            command[3] = 0x4E;
+                            self.code.extend_from_slice(&command);
+                            self.code.extend_from_slice(&reg_2.to_le_bytes());
+                            continue;
                         }
                         let [_, u2, u3, u4] = reg_2.to_be_bytes();
 
@@ -224,36 +491,38 @@ impl CodeGen {
                     self.code.extend_from_slice(&command);
                 }
                 Stmt::POP { reg } => {
-                    let reg = reg.token_type.get_reg();
+                    let reg = register(reg)?;
                     let mut command: [u8; 4] = [0; 4];
                     command[3] = 0x22;
-                    command[2] = reg.0 as u8;
+                    command[2] = reg;
                     self.code.extend_from_slice(&command);
                 }
-                Stmt::JMP { to } => {
-                    let val = to.literal.as_ref().unwrap();
-                    let val = (*self.table.get(val).unwrap()) as u32;
-                    let mut command: [u8; 4] = [0; 4];
-                    let [_, u2, u3, u4] = val.to_be_bytes();
-                    command[3] = 0x16;
-                    command[2] = u2;
-                    command[1] = u3;
-                    command[0] = u4;
-                    self.code.extend_from_slice(&command);
+                Stmt::JMP { to } => self.helper_jump(0x16, to)?,
+                Stmt::Call { to } => self.helper_jump(0x19, to)?,
+                Stmt::PUSHF => self.code.extend_from_slice(&[0x00, 0x00, 0x00, 0x5E]),
+                Stmt::POPF => self.code.extend_from_slice(&[0x00, 0x00, 0x00, 0x5F]),
+                Stmt::Enter { size } => {
+                    let [high, low] = immediate::<u16>(size)?.to_be_bytes();
+                    self.code.extend_from_slice(&[low, high, 0x00, 0x60]);
                 }
-                Stmt::Call { to } => {
-                    if to.token_type == TokenType::IDENT {
-                        let addr = (*self.table.get(to.literal.as_ref().unwrap()).unwrap()) as u32;
-                        let mut command: [u8; 4] = [0; 4];
-                        let [_, u1, u2, u3] = addr.to_be_bytes();
-                        command[3] = 0x19;
-                        command[2] = u1;
-                        command[1] = u2;
-                        command[0] = u3;
-                        self.code.extend_from_slice(&command);
+                Stmt::LEAVE => self.code.extend_from_slice(&[0x00, 0x00, 0x00, 0x61]),
+                Stmt::IRET => self.code.extend_from_slice(&[0x00, 0x00, 0x00, 0x6D]),
+                Stmt::Interrupt { number } => {
+                    let number = immediate::<u8>(number)?;
+                    self.code.extend_from_slice(&[0x00, 0x00, number, 0x62]);
+                }
+                Stmt::SYSCALL => self.code.extend_from_slice(&[0x00, 0x00, 0x00, 0x63]),
+                Stmt::Read { type_op, reg } => {
+                    let reg = register(reg)?;
+                    let op = if *type_op == TokenType::READ {
+                        0x64
                     } else {
-                        panic!("Expected a label");
-                    }
+                        0x65
+                    };
+                    self.code.extend_from_slice(&[0x00, 0x00, reg, op]);
+                }
+                Stmt::ReadLine { reg, address } => {
+                    self.helper_mem(0x66, 0x67, register(reg)?, address)?
                 }
                 Stmt::RET => {
                     let mut command: [u8; 4] = [0; 4];
@@ -267,36 +536,12 @@ impl CodeGen {
                 Stmt::SUB {
                     lhs_reg,
                     right_reg_imm,
-                } => {
-                    let reg = lhs_reg.token_type.get_reg();
-                    let mut command: [u8; 4] = [0; 4];
-                    if right_reg_imm.token_type != TokenType::INT {
-                        let reg_2 = right_reg_imm.token_type.get_reg();
-                        command[3] = 0x04;
-                        command[2] = reg.0 as u8;
-                        command[1] = reg_2.0 as u8;
-                    } else {
-                        let reg_2 = right_reg_imm
-                            .literal
-                            .as_ref()
-                            .unwrap()
-                            .parse::<i16>()
-                            .expect("parsing error");
-
-                        let [high, low] = reg_2.to_be_bytes();
-
-                        command[3] = 0x05;
-                        command[2] = reg.0 as u8;
-                        command[1] = high;
-                        command[0] = low;
-                    }
-                    self.code.extend_from_slice(&command);
-                }
+                } => self.helper_reg(0x04, 0x05, lhs_reg, right_reg_imm)?,
                 Stmt::Print { reg } => {
-                    let reg = reg.token_type.get_reg();
+                    let reg = register(reg)?;
                     let mut commad: [u8; 4] = [0; 4];
                     commad[3] = 0x11;
-                    commad[2] = reg.0 as u8;
+                    commad[2] = reg;
                     commad[1] = 0;
                     commad[0] = 0;
                     self.code.extend_from_slice(&commad);
@@ -304,159 +549,120 @@ impl CodeGen {
                 Stmt::ADD {
                     lhs_reg,
                     right_reg_imm,
-                } => {
-                    let reg = lhs_reg.token_type.get_reg();
-                    let mut command: [u8; 4] = [0; 4];
-                    if right_reg_imm.token_type != TokenType::INT {
-                        let reg_2 = right_reg_imm.token_type.get_reg();
-                        command[3] = 0x02;
-                        command[2] = reg.0 as u8;
-                        command[1] = reg_2.0 as u8;
-                    } else {
-                        let reg_2 = right_reg_imm
-                            .literal
-                            .as_ref()
-                            .unwrap()
-                            .parse::<i16>()
-                            .expect("parsing error");
-
-                        let [high, low] = reg_2.to_be_bytes();
-
-                        command[3] = 0x03;
-                        command[2] = reg.0 as u8;
-                        command[1] = high;
-                        command[0] = low;
-                    }
-                    self.code.extend_from_slice(&command);
-                }
+                } => self.helper_reg(0x02, 0x03, lhs_reg, right_reg_imm)?,
                 Stmt::CMP {
                     from_reg,
                     register_or_imm,
                 } => {
-                    let reg = from_reg.token_type.get_reg();
-                    let mut command: [u8; 4] = [0; 4];
-                    if [
-                        TokenType::R0,
-                        TokenType::R1,
-                        TokenType::R2,
-                        TokenType::R3,
-                        TokenType::R4,
-                        TokenType::R5,
-                        TokenType::R6,
-                        TokenType::R7,
-                    ]
-                    .contains(&register_or_imm.token_type){
-                        let reg_2 = register_or_imm.token_type.get_reg();
-                        command[3] = 0x06;
-                        command[2] = reg.0 as u8;
-                        command[1] = reg_2.0 as u8;
-                    } else if register_or_imm.token_type == TokenType::INT{
-                        let reg_2 = register_or_imm
-                            .literal
-                            .as_ref()
-                            .unwrap()
-                            .parse::<u16>()
-                            .expect("parsing error");
-
-                        let [high, low] = reg_2.to_be_bytes();
-
-                        command[3] = 0x18;
-                        command[2] = reg.0 as u8;
-                        command[1] = high;
-                        command[0] = low;
-                    }else if register_or_imm.token_type == TokenType::IDENT {
-                        let offset = self
-                            .data_tabel
-                            .get(register_or_imm.literal.as_ref().unwrap())
-                            .unwrap();
-                        let [high, low] = offset.to_be_bytes();
-                        command[3] = 0x36;
-                        command[2] = reg.0 as u8;
-                        command[1] = high;
-                        command[0] = low;
-
+                    if register_or_imm.token_type == TokenType::IDENT {
+                        self.helper_data(0x36, from_reg, register_or_imm)?;
+                    } else {
+                        self.helper_reg(0x06, 0x18, from_reg, register_or_imm)?;
                     }
-                    self.code.extend_from_slice(&command);
-                }
-                Stmt::JMPG { to } => {
-                    let val = to.literal.as_ref().unwrap();
-                    let val = (*self.table.get(val).unwrap()) as u32;
-                    let mut command: [u8; 4] = [0; 4];
-                    let [_, u2, u3, u4] = val.to_be_bytes();
-                    command[3] = 0x07;
-                    command[2] = u2;
-                    command[1] = u3;
-                    command[0] = u4;
-                    self.code.extend_from_slice(&command);
-                }
-                Stmt::JMPL { to } => {
-                    let val = to.literal.as_ref().unwrap();
-                    let val = (*self.table.get(val).unwrap()) as u32;
-                    let mut command: [u8; 4] = [0; 4];
-                    let [_, u2, u3, u4] = val.to_be_bytes();
-                    command[3] = 0x08;
-                    command[2] = u2;
-                    command[1] = u3;
-                    command[0] = u4;
-                    self.code.extend_from_slice(&command);
                 }
+                Stmt::JMPG { to } => self.helper_jump(0x07, to)?,
+                Stmt::JMPL { to } => self.helper_jump(0x08, to)?,
 
                 Stmt::MovLit {
                     from,
-                    register_or_imm_IDENT,
+                    register_or_imm_ident,
                 } => {
                     // 0x01 mov r1,10
                     // 0x17 mov r2, r1
-                    let mut command: [u8; 4] = [0; 4];
-                    let reg = from.token_type.get_reg();
-                    if [
-                        TokenType::R0,
-                        TokenType::R1,
-                        TokenType::R2,
-                        TokenType::R3,
-                        TokenType::R4,
-                        TokenType::R5,
-                        TokenType::R6,
-                        TokenType::R7,
-                    ]
-                    .contains(&register_or_imm_IDENT.token_type)
-                    {
-                        // 0x17 0xn 0xm 0x00
-                        let to = register_or_imm_IDENT.token_type.get_reg();
-                        command[3] = 0x17;
-                        command[2] = reg.0 as u8;
-                        command[1] = to.0 as u8;
-                        command[0] = 0x00;
-                    } else if register_or_imm_IDENT.token_type == TokenType::INT {
-                        let num = register_or_imm_IDENT
-                            .literal
-                            .as_ref()
-                            .unwrap()
-                            .parse::<i16>()
-                            .expect("number parsing error");
-                        let [high, low] = num.to_be_bytes();
-                        command[3] = 0x01;
-                        command[2] = reg.0 as u8;
-                        command[1] = high;
-                        command[0] = low;
-                    } else if register_or_imm_IDENT.token_type == TokenType::IDENT {
-                        let offset = self
-                            .data_tabel
-                            .get(register_or_imm_IDENT.literal.as_ref().unwrap())
-                            .unwrap();
-                        let [high, low] = offset.to_be_bytes();
-                        command[3] = 0x35;
-                        command[2] = reg.0 as u8;
-                        command[1] = high;
-                        command[0] = low;
+                    // 0x35 mov r2, ident
+                    if register_or_imm_ident.token_type == TokenType::IDENT {
+                        self.helper_data(0x35, from, register_or_imm_ident)?;
+                    } else {
+                        self.helper_reg(0x17, 0x01, from, register_or_imm_ident)?;
                     }
-                    self.code.extend_from_slice(&command);
+                }
+                Stmt::Load {
+                    type_op,
+                    reg,
+                    address,
+                } => {
+                    let n = width_index(*type_op);
+                    self.helper_mem(0x37 + n, 0x3D + n, register(reg)?, address)?;
+                }
+                Stmt::Store {
+                    type_op,
+                    reg,
+                    address,
+                } => {
+                    let n = width_index(*type_op);
+                    self.helper_mem(0x3A + n, 0x40 + n, register(reg)?, address)?;
+                }
+                Stmt::Lea {
+                    reg,
+                    address: Address::Label(ident),
+                } if self.is_code_label(ident) => self.helper_code_address(reg, ident)?,
+                Stmt::Lea { reg, address } => {
+                    self.helper_mem(0x43, 0x44, register(reg)?, address)?
+                }
+                Stmt::PrintChar { reg } => {
+                    let reg = register(reg)?;
+                    self.code.extend_from_slice(&[0x00, 0x00, reg, 0x69]);
+                }
+                Stmt::PrintString { address } => self.helper_mem(0x6A, 0x6B, 0, address)?,
+                Stmt::Alloc {
+                    reg,
+                    register_or_imm,
+                } => self.helper_reg(0x6E, 0x6F, reg, register_or_imm)?,
+                Stmt::Free { reg } => {
+                    let reg = register(reg)?;
+                    self.code.extend_from_slice(&[0x00, 0x00, reg, 0x70]);
                 }
                 Stmt::Halt { token: _ } => {
                     self.code.extend_from_slice(&[0x00, 0x00, 0x00, 0xFF]);
                 }
             }
         }
-        (&self.code, &self.data_code)
+        Ok((&self.code, &self.data_code))
+    }
+
+    /// Calling convention warnings, see [`lint::check`].
+    pub fn lint(&self) -> Vec<Diagnostic> {
+        lint::check(&self.statements, &self.table)
+    }
+
+    /// Generates the program and packages it with its symbol table.
+    pub fn object(&mut self) -> Result<Object, CodegenError> {
+        self.gen_()?;
+
+        let mut symbols: Vec<Symbol> = self
+            .table
+            .iter()
+            .map(|(name, index)| Symbol {
+                name: name.clone(),
+                kind: SymbolKind::Code,
+                width: 0,
+                value: self.addresses[*index],
+                size: 0,
+            })
+            .chain(self.data_tabel.iter().map(|(name, symbol)| Symbol {
+                name: name.clone(),
+                kind: symbol.kind,
+                width: symbol.width,
+                value: symbol.offset as u32,
+                size: symbol.len as u32,
+            }))
+            .collect();
+        // HashMap order is random, sort so the same source gives the same bytes
+        symbols.sort_by(|a, b| {
+            (a.kind as u8, a.value, &a.name).cmp(&(b.kind as u8, b.value, &b.name))
+        });
+        let entry = self
+            .table
+            .get(ENTRY_LABEL)
+            .map_or(0, |index| self.addresses[*index]);
+
+        Ok(Object {
+            isa_version: ISA_VERSION,
+            entry,
+            code: self.code.clone(),
+            data: self.data_code.clone(),
+            bss_size: self.bss_size,
+            symbols,
+        })
     }
 }
@@ -0,0 +1,164 @@
+use std::time::Duration;
+
+use crate::vm::VmConfig;
+
+pub const USAGE: &str = "\
+usage: vm_mini <command> [options]
+
+commands:
+    run <file.mm>               assemble and execute a source file
+    asm <file.mm> -o <out.bin>  assemble a source file into a binary image
+    exec <file.bin>             execute a binary image
+    disasm <file.bin>           disassemble a binary image back into source
+    debug <file.mm|file.bin>    step through a program interactively
+
+options (run, asm, debug):
+    --lint                      warn about calling convention violations
+
+options (run, exec, debug):
+    --input <file>              read program input from <file> instead of stdin;
+                                under `debug` the program has no input without it
+
+options (run, exec):
+    --regs                      dump the registers when the program stops
+    --flags                     dump the flag register when the program stops
+    --mem <addr>:<len>          dump <len> bytes of memory starting at <addr>
+    --heap                      report heap allocations and leaks when the program stops
+    --memory <bytes>            size of VM memory, 64K by default
+    --stack <bytes>             size of the stack at the top of memory, 16K by default
+    --max-steps <n>             stop the program after <n> instructions
+    --timeout <ms>              stop the program after <ms> milliseconds
+
+exit status is 0 on success, 1 when assembling or loading fails,
+2 on bad usage, 3 when the program faults and 4 when it runs out of
+steps or time; a program that calls the exit syscall ends with its own code";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Run { path: String },
+    Asm { path: String, output: String },
+    Exec { path: String },
+    Disasm { path: String },
+    Debug { path: String },
+    Help,
+}
+
+/// What to print once the VM stops.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Dump {
+    pub regs: bool,
+    pub flags: bool,
+    pub heap: bool,
+    pub memory: Vec<(usize, usize)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cli {
+    pub command: Command,
+    pub dump: Dump,
+    pub lint: bool,
+    pub input: Option<String>,
+    pub config: VmConfig,
+    pub max_steps: Option<u64>,
+    pub timeout: Option<Duration>,
+}
+
+pub fn parse(mut args: impl Iterator<Item = String>) -> Result<Cli, String> {
+    let Some(name) = args.next() else {
+        return Err("missing command".to_string());
+    };
+
+    let mut positional = vec![];
+    let mut output = None;
+    let mut dump = Dump::default();
+    let mut lint = false;
+    let mut input = None;
+    let mut config = VmConfig::default();
+    let mut max_steps = None;
+    let mut timeout = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => output = Some(args.next().ok_or("`-o` expects a file name")?),
+            "--regs" => dump.regs = true,
+            "--flags" => dump.flags = true,
+            "--heap" => dump.heap = true,
+            "--lint" => lint = true,
+            "--input" => input = Some(args.next().ok_or("`--input` expects a file name")?),
+            "--memory" => {
+                let size = args.next().ok_or("`--memory` expects a size in bytes")?;
+                config.memory_size = parse_number(&size)?;
+            }
+            "--stack" => {
+                let size = args.next().ok_or("`--stack` expects a size in bytes")?;
+                config.stack_size = parse_number(&size)?;
+            }
+            "--max-steps" => {
+                let steps = args.next().ok_or("`--max-steps` expects a number")?;
+                max_steps = Some(parse_number(&steps)? as u64);
+            }
+            "--timeout" => {
+                let millis = args.next().ok_or("`--timeout` expects milliseconds")?;
+                timeout = Some(Duration::from_millis(parse_number(&millis)? as u64));
+            }
+            "--mem" => {
+                let range = args.next().ok_or("`--mem` expects <addr>:<len>")?;
+                dump.memory.push(parse_range(&range)?);
+            }
+            flag if flag.starts_with('-') => return Err(format!("unknown option `{flag}`")),
+            _ => positional.push(arg),
+        }
+    }
+
+    config.validate().map_err(|err| err.to_string())?;
+
+    let path = |positional: &mut Vec<String>| match positional.len() {
+        1 => Ok(positional.remove(0)),
+        0 => Err(format!("`{name}` expects a file")),
+        _ => Err(format!("`{name}` expects a single file")),
+    };
+    let command = match name.as_str() {
+        "run" => Command::Run {
+            path: path(&mut positional)?,
+        },
+        "asm" => Command::Asm {
+            path: path(&mut positional)?,
+            output: output.ok_or("`asm` expects `-o <out.bin>`")?,
+        },
+        "exec" => Command::Exec {
+            path: path(&mut positional)?,
+        },
+        "disasm" => Command::Disasm {
+            path: path(&mut positional)?,
+        },
+        "debug" => Command::Debug {
+            path: path(&mut positional)?,
+        },
+        "help" | "-h" | "--help" => Command::Help,
+        other => return Err(format!("unknown command `{other}`")),
+    };
+    Ok(Cli {
+        command,
+        dump,
+        lint,
+        input,
+        config,
+        max_steps,
+        timeout,
+    })
+}
+
+fn parse_range(range: &str) -> Result<(usize, usize), String> {
+    let (addr, len) = range
+        .split_once(':')
+        .ok_or(format!("expected <addr>:<len>, found `{range}`"))?;
+    Ok((parse_number(addr)?, parse_number(len)?))
+}
+
+/// Parses a decimal or `0x` prefixed hexadecimal number.
+pub fn parse_number(number: &str) -> Result<usize, String> {
+    let parsed = match number.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => number.parse::<usize>(),
+    };
+    parsed.map_err(|_| format!("invalid number `{number}`"))
+}
@@ -0,0 +1,249 @@
+use std::{
+    collections::BTreeSet,
+    error::Error,
+    io::{self, BufRead, Read, Write},
+};
+
+use crate::{
+    cli::parse_number,
+    disasm::{Disassembler, Reg},
+    object::{Object, SymbolKind},
+    vm::{Status, VM, VmError},
+};
+
+const HELP: &str = "\
+commands:
+    s, step                 execute one instruction
+    n, next                 execute one instruction, stepping over calls
+    c, continue             run until a breakpoint, halt or fault
+    f, finish               run until the current call returns
+    b, break [addr|label]   set a breakpoint, or list them without an argument
+    d, delete <addr|label>  remove a breakpoint
+    reg                     print the registers, sp and fp included
+    flag                    print the flag register
+    pc                      print the program counter
+    sp                      print the stack pointer
+    bt, stack               print the call stack
+    x <addr|label> [len]    print memory, 16 bytes unless len is given
+    l, list                 print the current instruction
+    q, quit                 leave the debugger";
+
+/// Why the program stopped after a resume command.
+enum Stop {
+    Done,
+    Breakpoint,
+    Halted,
+    Exited(i32),
+    Fault(VmError),
+}
+
+/// Line-oriented debugger driving a [`VM`] one [`VM::step`] at a time.
+pub struct Debugger<'a> {
+    vm: VM,
+    disasm: Disassembler<'a>,
+    object: &'a Object,
+    breakpoints: BTreeSet<u32>,
+    finished: bool,
+}
+
+impl<'a> Debugger<'a> {
+    pub fn new(object: &'a Object) -> Result<Self, Box<dyn Error>> {
+        let mut vm = VM::default();
+        vm.load(object)?;
+        // stdin carries the debugger's commands, so the program reads nothing
+        // unless given its own input
+        vm.set_input(io::empty());
+        Ok(Self {
+            vm,
+            disasm: Disassembler::new(object)?,
+            object,
+            breakpoints: BTreeSet::new(),
+            finished: false,
+        })
+    }
+
+    /// Feeds the program's input from `input`.
+    pub fn set_input(&mut self, input: impl Read + 'static) {
+        self.vm.set_input(input);
+    }
+
+    /// Reads commands from `input` until `quit` or end of input.
+    pub fn run(&mut self, input: impl BufRead, mut out: impl Write) -> io::Result<()> {
+        self.location(&mut out)?;
+        write!(out, "(vmdb) ")?;
+        out.flush()?;
+        for line in input.lines() {
+            let line = line?;
+            let words: Vec<&str> = line.split_whitespace().collect();
+            if let Some((command, args)) = words.split_first() {
+                if matches!(*command, "q" | "quit") {
+                    break;
+                }
+                self.command(command, args, &mut out)?;
+            }
+            write!(out, "(vmdb) ")?;
+            out.flush()?;
+        }
+        writeln!(out)
+    }
+
+    fn command(&mut self, command: &str, args: &[&str], out: &mut impl Write) -> io::Result<()> {
+        match command {
+            "s" | "step" => self.resume(|_| true, out),
+            "n" | "next" => {
+                let depth = self.vm.call_stack().len();
+                self.resume(move |vm| vm.call_stack().len() <= depth, out)
+            }
+            "c" | "continue" => self.resume(|_| false, out),
+            "f" | "finish" => {
+                let depth = self.vm.call_stack().len();
+                if depth == 0 {
+                    return writeln!(out, "not inside a call");
+                }
+                self.resume(move |vm| vm.call_stack().len() < depth, out)
+            }
+            "b" | "break" => match args.first() {
+                None => {
+                    for address in &self.breakpoints {
+                        writeln!(out, "{address:#06x} <{}>", self.disasm.symbolize(*address))?;
+                    }
+                    Ok(())
+                }
+                Some(arg) => match self.code_address(arg) {
+                    Some(address) => {
+                        self.breakpoints.insert(address);
+                        writeln!(out, "breakpoint at {address:#06x}")
+                    }
+                    None => writeln!(out, "unknown address `{arg}`"),
+                },
+            },
+            "d" | "delete" => match args.first().and_then(|arg| self.code_address(arg)) {
+                Some(address) if self.breakpoints.remove(&address) => {
+                    writeln!(out, "deleted breakpoint at {address:#06x}")
+                }
+                _ => writeln!(out, "no such breakpoint"),
+            },
+            "reg" => {
+                for (i, value) in self.vm.reg.iter().enumerate() {
+                    writeln!(out, "{} = {value} ({:#010x})", Reg(i as u8), *value as u32)?;
+                }
+                Ok(())
+            }
+            "flag" => writeln!(
+                out,
+                "flag = {:#010b} [{}]",
+                self.vm.flags(),
+                self.vm.describe_flags()
+            ),
+            "pc" => {
+                let pc = self.vm.pc();
+                writeln!(out, "pc = {pc:#06x} <{}>", self.disasm.symbolize(pc))
+            }
+            "sp" => writeln!(out, "sp = {:#06x}", self.vm.sp()),
+            "bt" | "stack" => {
+                let pc = self.vm.pc();
+                writeln!(out, "#0 {pc:#06x} in {}", self.disasm.symbolize(pc))?;
+                for (i, frame) in self.vm.call_stack().iter().rev().enumerate() {
+                    writeln!(
+                        out,
+                        "#{} {:#06x} in {}, calling {}",
+                        i + 1,
+                        frame.caller,
+                        self.disasm.symbolize(frame.caller),
+                        self.disasm.symbolize(frame.callee)
+                    )?;
+                }
+                Ok(())
+            }
+            "x" => {
+                let Some(start) = args.first().and_then(|arg| self.memory_address(arg)) else {
+                    return writeln!(out, "usage: x <addr|label> [len]");
+                };
+                let len = match args.get(1) {
+                    Some(len) => parse_number(len).unwrap_or(16),
+                    None => 16,
+                };
+                let end = start.saturating_add(len).min(self.vm.memory.len());
+                for (i, row) in self.vm.memory[start.min(end)..end].chunks(16).enumerate() {
+                    writeln!(out, "{:04x}: {:02x?}", start + i * 16, row)?;
+                }
+                Ok(())
+            }
+            "l" | "list" => self.location(out),
+            "h" | "help" => writeln!(out, "{HELP}"),
+            other => writeln!(out, "unknown command `{other}`, try `help`"),
+        }
+    }
+
+    /// Steps until `done` holds, a breakpoint is reached or the program
+    /// stops. Always executes at least one instruction so that resuming from
+    /// a breakpoint makes progress.
+    fn resume(&mut self, done: impl Fn(&VM) -> bool, out: &mut impl Write) -> io::Result<()> {
+        if self.finished {
+            return writeln!(out, "the program is not running");
+        }
+        let stop = loop {
+            match self.vm.step() {
+                Err(err) => break Stop::Fault(err),
+                Ok(Status::Halted) => break Stop::Halted,
+                Ok(Status::Exited(code)) => break Stop::Exited(code),
+                Ok(Status::Running) => {}
+            }
+            if done(&self.vm) {
+                break Stop::Done;
+            }
+            if self.breakpoints.contains(&self.vm.pc()) {
+                break Stop::Breakpoint;
+            }
+        };
+
+        match stop {
+            Stop::Done => self.location(out),
+            Stop::Breakpoint => {
+                writeln!(out, "breakpoint hit")?;
+                self.location(out)
+            }
+            Stop::Halted => {
+                self.finished = true;
+                writeln!(out, "program halted")
+            }
+            Stop::Exited(code) => {
+                self.finished = true;
+                writeln!(out, "program exited with code {code}")
+            }
+            Stop::Fault(err) => {
+                self.finished = true;
+                writeln!(out, "program faulted: {err}")
+            }
+        }
+    }
+
+    fn location(&self, out: &mut impl Write) -> io::Result<()> {
+        let pc = self.vm.pc();
+        let text = match self.disasm.instruction(pc) {
+            Ok((text, _)) => text,
+            Err(err) => format!("<{err}>"),
+        };
+        writeln!(out, "{pc:#06x} <{}>: {text}", self.disasm.symbolize(pc))
+    }
+
+    fn code_address(&self, arg: &str) -> Option<u32> {
+        self.disasm
+            .address_of(arg)
+            .or_else(|| parse_number(arg).ok().map(|address| address as u32))
+    }
+
+    /// A number, a code label or a data or bss symbol.
+    fn memory_address(&self, arg: &str) -> Option<usize> {
+        let data = self
+            .object
+            .symbols
+            .iter()
+            .find(|symbol| symbol.kind != SymbolKind::Code && symbol.name == arg)
+            .map(|symbol| match symbol.kind {
+                SymbolKind::Bss => self.vm.config().bss_start + symbol.value as usize,
+                _ => self.vm.config().data_start + symbol.value as usize,
+            });
+        data.or_else(|| self.code_address(arg).map(|address| address as usize))
+    }
+}
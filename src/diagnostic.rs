@@ -0,0 +1,48 @@
+/// A single error report pointing at a line and column of the source,
+/// rendered in the same shape rustc uses:
+///
+/// ```text
+/// error: unexpected token INT
+///  --> asm1.mm:3:5
+///   |
+/// 3 | mov 5, r1
+///   |     ^ expected register r0..r7, sp, fp, found INT
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub message: String,
+    pub line: usize,
+    pub column: usize,
+    pub hint: String,
+}
+
+impl Diagnostic {
+    pub fn render(&self, path: &str, source: &str) -> String {
+        self.render_as("error", path, source)
+    }
+
+    /// Same shape as [`Diagnostic::render`], for lints that do not stop
+    /// the assembler.
+    pub fn render_warning(&self, path: &str, source: &str) -> String {
+        self.render_as("warning", path, source)
+    }
+
+    fn render_as(&self, level: &str, path: &str, source: &str) -> String {
+        let text = source
+            .lines()
+            .nth(self.line.saturating_sub(1))
+            .unwrap_or_default();
+        let gutter = " ".repeat(self.line.to_string().len());
+        // keep tabs so the caret lines up with the echoed source line
+        let indent: String = text
+            .chars()
+            .take(self.column.saturating_sub(1))
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+
+        format!(
+            "{level}: {}\n{gutter}--> {path}:{}:{}\n{gutter} |\n{} | {text}\n{gutter} | {indent}^ {}\n",
+            self.message, self.line, self.column, self.line, self.hint
+        )
+    }
+}
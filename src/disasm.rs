@@ -0,0 +1,673 @@
+use std::{collections::HashMap, fmt, fmt::Write};
+
+use crate::{
+    backend::ENTRY_LABEL,
+    object::{Object, Symbol, SymbolKind},
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DisasmError {
+    InvalidOpcode { address: u32, opcode: u8 },
+    Truncated { address: u32 },
+}
+
+impl fmt::Display for DisasmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DisasmError::InvalidOpcode { address, opcode } => {
+                write!(f, "invalid opcode {opcode:#04x} at {address:#06x}")
+            }
+            DisasmError::Truncated { address } => {
+                write!(f, "truncated instruction at {address:#06x}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for DisasmError {}
+
+/// Turns the code section of an [`Object`] back into source text.
+///
+/// Labels come from the symbol table when the object has one; jump targets
+/// without a symbol get synthetic `L_xxxx` names and data without a symbol
+/// gets `d_xxxx` names. Jumps are PC-relative, so a label is printed before
+/// the instruction at its address and the listing reassembles to the same
+/// bytes.
+pub struct Disassembler<'a> {
+    object: &'a Object,
+    /// Every name of each code address, since labels take no space and
+    /// several can share one.
+    labels: HashMap<u32, Vec<String>>,
+    data_names: HashMap<u32, String>,
+    /// Element width and total size of each named data declaration.
+    data_layout: HashMap<u32, (u8, u32)>,
+    /// Data declarations without bytes, e.g. `times 0 0`, by offset.
+    empty_data: Vec<&'a Symbol>,
+    /// Every named `.data` declaration ordered by offset.
+    data: Vec<&'a Symbol>,
+    /// `.bss` reservations ordered by offset.
+    bss: Vec<&'a Symbol>,
+}
+
+impl<'a> Disassembler<'a> {
+    pub fn new(object: &'a Object) -> Result<Self, DisasmError> {
+        let mut disasm = Self {
+            object,
+            labels: HashMap::new(),
+            data_names: HashMap::new(),
+            data_layout: HashMap::new(),
+            empty_data: vec![],
+            data: vec![],
+            bss: vec![],
+        };
+        for symbol in &object.symbols {
+            if symbol.kind == SymbolKind::Data {
+                disasm.data.push(symbol);
+                if symbol.size == 0 {
+                    disasm.empty_data.push(symbol);
+                    continue;
+                }
+                disasm
+                    .data_layout
+                    .insert(symbol.value, (symbol.width, symbol.size));
+            }
+            match symbol.kind {
+                SymbolKind::Code => disasm
+                    .labels
+                    .entry(symbol.value)
+                    .or_default()
+                    .push(symbol.name.clone()),
+                SymbolKind::Data => {
+                    disasm
+                        .data_names
+                        .entry(symbol.value)
+                        .or_insert(symbol.name.clone());
+                }
+                SymbolKind::Bss => disasm.bss.push(symbol),
+            }
+        }
+        disasm.bss.sort_by_key(|symbol| (symbol.value, symbol.size));
+        if object.entry != 0 {
+            disasm
+                .labels
+                .entry(object.entry)
+                .or_insert(vec![ENTRY_LABEL.to_string()]);
+        }
+        for offset in 0..object.data.len() as u32 {
+            disasm
+                .data_names
+                .entry(offset)
+                .or_insert(format!("d_{offset:04x}"));
+        }
+
+        let mut address = 0;
+        while (address as usize) < object.code.len() {
+            let [opcode, inst2, inst3, inst4] = disasm.fetch(address)?;
+            if is_jump(opcode) || opcode == 0x6C {
+                let target = match opcode {
+                    0x6C => code_address(address, inst3, inst4),
+                    _ => target(address, inst2, inst3, inst4),
+                };
+                disasm
+                    .labels
+                    .entry(target)
+                    .or_insert(vec![format!("L_{target:04x}")]);
+            }
+            address += length(opcode, inst2);
+        }
+        Ok(disasm)
+    }
+
+    /// Instruction word at `address`, opcode first.
+    fn fetch(&self, address: u32) -> Result<[u8; 4], DisasmError> {
+        let at = address as usize;
+        let bytes = self
+            .object
+            .code
+            .get(at..at + 4)
+            .ok_or(DisasmError::Truncated { address })?;
+        let word = u32::from_le_bytes(bytes.try_into().unwrap());
+        Ok(word.to_be_bytes())
+    }
+
+    /// The name operands use for `address`, the first of its labels.
+    pub fn label(&self, address: u32) -> Option<&str> {
+        self.labels_at(address).first().map(String::as_str)
+    }
+
+    /// Every label at `address`.
+    pub fn labels_at(&self, address: u32) -> &[String] {
+        self.labels.get(&address).map_or(&[], Vec::as_slice)
+    }
+
+    pub fn address_of(&self, label: &str) -> Option<u32> {
+        self.labels
+            .iter()
+            .find(|(_, names)| names.iter().any(|name| name == label))
+            .map(|(address, _)| *address)
+    }
+
+    /// `address` relative to the closest label before it, e.g. `loop+8`.
+    pub fn symbolize(&self, address: u32) -> String {
+        let closest = self
+            .labels
+            .iter()
+            .filter(|(start, _)| **start <= address)
+            .max_by_key(|(start, _)| **start)
+            .and_then(|(start, names)| Some((start, names.first()?)));
+        match closest {
+            Some((start, name)) if *start == address => name.clone(),
+            Some((start, name)) => format!("{name}+{}", address - start),
+            None => format!("{address:#06x}"),
+        }
+    }
+
+    /// Decodes the instruction at `address`, returning its text and size in
+    /// bytes.
+    pub fn instruction(&self, address: u32) -> Result<(String, u32), DisasmError> {
+        let [opcode, inst2, inst3, inst4] = self.fetch(address)?;
+        let len = length(opcode, inst2);
+        // a wide immediate lives in the next word, stored little-endian
+        let (reg, imm) = if len == 8 {
+            let extension = u32::from_be_bytes(self.fetch(address + 4)?) as i32;
+            (inst2 & 0x7F, extension)
+        } else {
+            (inst2, i16::from_be_bytes([inst3, inst4]) as i32)
+        };
+        let (reg, rn, rm) = (Reg(reg), Reg(inst2), Reg(inst3));
+        let label = |target: u32| {
+            self.label(target)
+                .map_or(format!("L_{target:04x}"), str::to_string)
+        };
+        // bit 6 of inst2 selects the bss segment. Several names can share an
+        // offset when some are empty: `mov`/`cmp` need the one of the encoded
+        // width, otherwise a declaration with room is preferred.
+        let data = |offset: u32, width: Option<u8>| {
+            let (symbols, prefix) = match inst2 & 0x40 {
+                0 => (&self.data, 'd'),
+                _ => (&self.bss, 'b'),
+            };
+            symbols
+                .iter()
+                .filter(|symbol| symbol.value == offset)
+                .min_by_key(|symbol| (width.is_some_and(|w| w != symbol.width), symbol.size == 0))
+                .map_or(format!("{prefix}_{offset:04x}"), |symbol| symbol.name.clone())
+        };
+        // bits 4-5 of inst2 hold log2 of the width `mov`/`cmp` read
+        let width = Some(1 << (inst2 >> 4 & 3));
+        let data_offset = u16::from_be_bytes([inst3, inst4]) as u32;
+
+        let text = match opcode {
+            0x00 => "nop".to_string(),
+            0xFF => "halt".to_string(),
+            0x01 => format!("mov {reg}, {imm}"),
+            0x02 => format!("add {rn}, {rm}"),
+            0x03 => format!("add {reg}, {imm}"),
+            0x04 => format!("sub {rn}, {rm}"),
+            0x05 => format!("sub {reg}, {imm}"),
+            0x06 => format!("cmp {rn}, {rm}"),
+            0x11 => format!("print {rn}"),
+            0x12 => format!("mul {reg}, {imm}"),
+            0x13 => format!("div {rn}, {rm}"),
+            0x17 => format!("mov {rn}, {rm}"),
+            0x18 => format!("cmp {reg}, {imm}"),
+            0x20 => "ret".to_string(),
+            0x21 => {
+                let sign = if inst2 & 0x80 != 0 { 0xFF } else { 0x00 };
+                format!("push {}", i32::from_be_bytes([sign, inst2, inst3, inst4]))
+            }
+            0x4E => format!("push {imm}"),
+            0x59 => format!("inc {rn}"),
+            0x5A => format!("dec {rn}"),
+            0x5B => format!("neg {rn}"),
+            0x5E => "pushf".to_string(),
+            0x5F => "popf".to_string(),
+            0x60 => format!("enter {}", u16::from_be_bytes([inst3, inst4])),
+            0x61 => "leave".to_string(),
+            0x62 => format!("int {inst2}"),
+            0x63 => "syscall".to_string(),
+            0x64 => format!("read {rn}"),
+            0x65 => format!("readc {rn}"),
+            0x69 => format!("printc {rn}"),
+            0x6C => format!("lea {rn}, [{}]", label(code_address(address, inst3, inst4))),
+            0x6D => "iret".to_string(),
+            0x6E => format!("alloc {rn}, {rm}"),
+            0x6F => format!("alloc {reg}, {imm}"),
+            0x70 => format!("free {rn}"),
+            0x5C => format!("test {rn}, {rm}"),
+            0x5D => format!("test {reg}, {imm}"),
+            0x4F..=0x58 => {
+                let mnemonic = ["shl", "shr", "sar", "rol", "ror"][(opcode - 0x4F) as usize / 2];
+                if (opcode - 0x4F) % 2 == 0 {
+                    format!("{mnemonic} {rn}, {rm}")
+                } else {
+                    format!("{mnemonic} {reg}, {imm}")
+                }
+            }
+            0x22 => format!("pop {rn}"),
+            0x23 => format!("push {rn}"),
+            0x24 => format!("and {rn}, {rm}"),
+            0x25 => format!("and {reg}, {imm}"),
+            0x26 => format!("or {rn}, {rm}"),
+            0x27 => format!("or {reg}, {imm}"),
+            0x28 => format!("xor {rn}, {rm}"),
+            0x29 => format!("xor {reg}, {imm}"),
+            0x30 => format!("not {rn}"),
+            0x31 => format!("mul {rn}, {rm}"),
+            0x32 => format!("div {reg}, {imm}"),
+            0x33 => format!("mod {rn}, {rm}"),
+            0x34 => format!("mod {reg}, {imm}"),
+            0x35 => format!("mov {}, {}", Reg(inst2 & 0x0F), data(data_offset, width)),
+            0x36 => format!("cmp {}, {}", Reg(inst2 & 0x0F), data(data_offset, width)),
+            0x37..=0x3C | 0x43 | 0x66 | 0x6A => {
+                let (d, base) = (Reg(inst2 >> 4), Reg(inst2 & 0x0F));
+                let operand = match imm {
+                    0 => format!("[{base}]"),
+                    imm if imm < 0 => format!("[{base} - {}]", -imm),
+                    imm => format!("[{base} + {imm}]"),
+                };
+                memory_operands(opcode, d, &operand)
+            }
+            0x3D..=0x42 | 0x44 | 0x67 | 0x6B => {
+                let operand = format!("[{}]", data(data_offset, None));
+                memory_operands(opcode, Reg(inst2 & 0x0F), &operand)
+            }
+            op if is_jump(op) => {
+                format!(
+                    "{} {}",
+                    jump_mnemonic(op),
+                    label(target(address, inst2, inst3, inst4))
+                )
+            }
+            _ => return Err(DisasmError::InvalidOpcode { address, opcode }),
+        };
+        Ok((text, len))
+    }
+
+    /// Whether the byte at `offset` is alignment the assembler inserts by
+    /// itself: zero, unnamed, and followed by an aligned word or dword.
+    fn is_padding(&self, offset: u32) -> bool {
+        if self.data_layout.contains_key(&offset) || self.object.data[offset as usize] != 0 {
+            return false;
+        }
+        // empty declarations are aligned too, so every name at `next` counts
+        let at = |next: u32| self.data.iter().filter(move |symbol| symbol.value == next);
+        let next = (offset + 1..offset + 4).find(|&next| at(next).next().is_some());
+        next.is_some_and(|next| {
+            at(next).any(|symbol| {
+                let width = symbol.width as u32;
+                width > 1 && next.is_multiple_of(width) && next - offset < width
+            })
+        })
+    }
+
+    /// Full listing with `.data` and `.code` sections, ready to reassemble.
+    pub fn listing(&self) -> Result<String, DisasmError> {
+        let mut out = String::new();
+        if !self.object.data.is_empty() || !self.empty_data.is_empty() {
+            out.push_str(".data\n");
+            let data = &self.object.data;
+            let mut offset = 0;
+            let empty = |out: &mut String, at: std::ops::Range<usize>| {
+                for symbol in self
+                    .empty_data
+                    .iter()
+                    .filter(|symbol| at.contains(&(symbol.value as usize)))
+                {
+                    let directive = match symbol.width {
+                        2 => "dw",
+                        4 => "dd",
+                        _ => "db",
+                    };
+                    writeln!(out, "{directive} {} times 0 0", symbol.name).unwrap();
+                }
+            };
+            while offset < data.len() {
+                empty(&mut out, offset..offset + 1);
+                if self.is_padding(offset as u32) {
+                    offset += 1;
+                    continue;
+                }
+                let name = &self.data_names[&(offset as u32)];
+                let (width, size) = match self.data_layout.get(&(offset as u32)) {
+                    Some(&(width, size))
+                        if width > 0
+                            && size % width as u32 == 0
+                            && offset + size as usize <= data.len() =>
+                    {
+                        (width as usize, size as usize)
+                    }
+                    _ => (1, 1),
+                };
+                let directive = match width {
+                    2 => "dw",
+                    4 => "dd",
+                    _ => "db",
+                };
+                let values = render_values(&data[offset..offset + size], width);
+                writeln!(out, "{directive} {name} {values}").unwrap();
+                offset += size;
+            }
+            empty(&mut out, offset..usize::MAX);
+            out.push('\n');
+        }
+
+        if self.object.bss_size > 0 || !self.bss.is_empty() {
+            out.push_str(".bss\n");
+            let mut offset = 0;
+            for symbol in &self.bss {
+                let width = symbol.width.max(1) as u32;
+                // gaps the assembler leaves for alignment need no declaration
+                if symbol.value > offset
+                    && (symbol.value - offset >= width || !symbol.value.is_multiple_of(width))
+                {
+                    writeln!(out, "resb b_{offset:04x} {}", symbol.value - offset).unwrap();
+                }
+                let directive = match width {
+                    2 => "resw",
+                    4 => "resd",
+                    _ => "resb",
+                };
+                writeln!(out, "{directive} {} {}", symbol.name, symbol.size / width).unwrap();
+                offset = offset.max(symbol.value + symbol.size);
+            }
+            if self.object.bss_size > offset {
+                writeln!(out, "resb b_{offset:04x} {}", self.object.bss_size - offset).unwrap();
+            }
+            out.push('\n');
+        }
+
+        out.push_str(".code\n");
+        let mut address = 0;
+        while (address as usize) < self.object.code.len() {
+            for label in self.labels_at(address) {
+                writeln!(out, "{label}:").unwrap();
+            }
+            let (text, len) = self.instruction(address)?;
+            writeln!(out, "    {text:<24}; {address:04x}").unwrap();
+            address += len;
+        }
+        // labels after the last instruction
+        for label in self.labels_at(address) {
+            writeln!(out, "{label}:").unwrap();
+        }
+        Ok(out)
+    }
+}
+
+/// Size in bytes of the instruction starting with `opcode`: immediate forms
+/// with bit 7 of `inst2` set and `push imm32` carry an extension word.
+fn length(opcode: u8, inst2: u8) -> u32 {
+    let immediate = matches!(
+        opcode,
+        0x01 | 0x03
+            | 0x05
+            | 0x12
+            | 0x18
+            | 0x25
+            | 0x27
+            | 0x29
+            | 0x32
+            | 0x34
+            | 0x50
+            | 0x52
+            | 0x54
+            | 0x56
+            | 0x58
+            | 0x5D
+            | 0x6F
+    );
+    if (immediate && inst2 & 0x80 != 0) || opcode == 0x4E {
+        8
+    } else {
+        4
+    }
+}
+
+/// Register operand, `sp` and `fp` follow r0..r7.
+pub struct Reg(pub u8);
+
+impl fmt::Display for Reg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            8 => write!(f, "sp"),
+            9 => write!(f, "fp"),
+            n => write!(f, "r{n}"),
+        }
+    }
+}
+
+fn is_jump(opcode: u8) -> bool {
+    matches!(
+        opcode,
+        0x07 | 0x08 | 0x09 | 0x10 | 0x14 | 0x15 | 0x16 | 0x19 | 0x45..=0x4D | 0x68
+    )
+}
+
+fn jump_mnemonic(opcode: u8) -> &'static str {
+    match opcode {
+        0x07 => "jmpg",
+        0x08 => "jmpl",
+        0x09 => "jmpge",
+        0x10 => "jmple",
+        0x14 => "jmpz",
+        0x15 => "jmpnz",
+        0x16 => "jmp",
+        0x19 => "call",
+        0x45 => "jc",
+        0x46 => "jnc",
+        0x47 => "jo",
+        0x48 => "ja",
+        0x49 => "jb",
+        0x4A => "jae",
+        0x4B => "jbe",
+        0x4C => "je",
+        0x4D => "jne",
+        0x68 => "jeof",
+        _ => unreachable!("not a jump opcode"),
+    }
+}
+
+/// Values of a data declaration, with runs of printable bytes as strings.
+fn render_values(bytes: &[u8], width: usize) -> String {
+    let mut values = vec![];
+    let mut at = 0;
+    while at < bytes.len() {
+        let run = match width {
+            1 => bytes[at..]
+                .iter()
+                .take_while(|byte| byte.is_ascii_graphic() || b" \n\t\r".contains(byte))
+                .count(),
+            _ => 0,
+        };
+        if run >= 2 {
+            let text: String = bytes[at..at + run]
+                .iter()
+                .map(|byte| (*byte as char).escape_default().to_string())
+                .collect();
+            values.push(format!("\"{text}\""));
+            at += run;
+            continue;
+        }
+        let value = match width {
+            2 => i16::from_le_bytes([bytes[at], bytes[at + 1]]) as i32,
+            4 => i32::from_le_bytes(bytes[at..at + 4].try_into().unwrap()),
+            _ => bytes[at] as i8 as i32,
+        };
+        values.push(value.to_string());
+        at += width;
+    }
+    values.join(", ")
+}
+
+/// A memory instruction with its register, if it has one, and `operand`.
+fn memory_operands(opcode: u8, reg: Reg, operand: &str) -> String {
+    match opcode {
+        0x6A | 0x6B => format!("prints {operand}"),
+        _ => format!("{} {reg}, {operand}", memory_mnemonic(opcode)),
+    }
+}
+
+fn memory_mnemonic(opcode: u8) -> &'static str {
+    match opcode {
+        0x37 | 0x3D => "loadb",
+        0x38 | 0x3E => "loadw",
+        0x39 | 0x3F => "loadd",
+        0x3A | 0x40 => "storeb",
+        0x3B | 0x41 => "storew",
+        0x3C | 0x42 => "stored",
+        0x43 | 0x44 => "lea",
+        0x66 | 0x67 => "readln",
+        _ => unreachable!("not a memory opcode"),
+    }
+}
+
+/// `lea` of a code label holds a signed 16-bit offset from the instruction.
+fn code_address(address: u32, inst3: u8, inst4: u8) -> u32 {
+    address.wrapping_add_signed(i16::from_be_bytes([inst3, inst4]) as i32)
+}
+
+/// Jump operands hold a signed 24-bit byte offset from the jump itself.
+fn target(address: u32, inst2: u8, inst3: u8, inst4: u8) -> u32 {
+    let offset = i32::from_be_bytes([inst2, inst3, inst4, 0x00]) >> 8;
+    address.wrapping_add_signed(offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{backend::CodeGen, parser::Parser, scanner::Scanner};
+
+    fn assemble(source: &str) -> Object {
+        let mut scanner = Scanner::new(source);
+        let tokens = scanner.parse().unwrap().to_vec();
+        CodeGen::new(Parser::new(tokens)).unwrap().object().unwrap()
+    }
+
+    /// Every mnemonic in each operand form, wide immediates and names that
+    /// share an offset with an empty declaration.
+    const PROGRAM: &str = r#"
+.data
+dd big 2
+db empty times 0 0
+dw words 1, 2, 3
+db msg "hi\n", 0
+db pad 7
+dd late times 0 0
+.bss
+resb none 0
+resd buf 4
+resw zw 0
+.code
+start:
+    mov r1, 5
+    mov r2, 100000
+    mov r3, -70000
+    mov r4, r1
+    mov r5, big
+    mov r6, empty
+    mov r7, words
+    mov r1, late
+    mov r2, none
+    mov r3, buf
+    mov sp, fp
+    add r1, r2
+    add r1, 7
+    add r1, 123456
+    sub r1, r2
+    sub r1, -99999
+    cmp r1, r2
+    cmp r1, 77777
+    cmp r1, zw
+    mul r1, r2
+    mul r1, 65537
+    div r1, r2
+    div r1, 3
+    mod r1, r2
+    mod r1, 9
+    and r1, r2
+    and r1, 255
+    or r1, r2
+    or r1, 70000
+    xor r1, r2
+    xor r1, 1
+    not r1
+    inc r1
+    dec r1
+    neg r1
+    test r1, r2
+    test r1, 8
+    shl r1, r2
+    shl r1, 2
+    shr r1, r2
+    shr r1, 3
+    sar r1, r2
+    sar r1, 4
+    rol r1, r2
+    rol r1, 5
+    ror r1, r2
+    ror r1, 6
+    pushf
+    popf
+    push r1
+    push 200000
+    pop r1
+    call func
+    enter 8
+    leave
+    int 1
+    syscall
+    print r1
+    printc r1
+    prints [msg]
+    prints [r1]
+    read r1
+    readc r1
+    readln r1, [buf]
+    readln r1, [r2 + 4]
+    loadb r1, [msg]
+    loadw r1, [words]
+    loadd r1, [big]
+    loadd r1, [fp - 4]
+    storeb r1, [empty]
+    storew r1, [zw]
+    stored r1, [none]
+    stored r1, [sp + 8]
+    lea r1, [buf]
+    lea r1, [r2 - 12]
+    lea r1, [func]
+    alloc r1, r2
+    alloc r1, 100000
+    free r1
+    jmp start
+    jmpg start
+    jmpl start
+    jmpge start
+    jmple start
+    jmpz start
+    jmpnz start
+    je start
+    jne start
+    jc start
+    jnc start
+    jo start
+    ja start
+    jae start
+    jb start
+    jbe start
+    jeof start
+    nop
+    halt
+func:
+    ret
+handler:
+    iret
+"#;
+
+    #[test]
+    fn listing_reassembles_to_the_same_object() {
+        let object = assemble(PROGRAM);
+        let listing = Disassembler::new(&object).unwrap().listing().unwrap();
+        assert_eq!(assemble(&listing).to_bytes(), object.to_bytes(), "{listing}");
+    }
+}
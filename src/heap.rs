@@ -0,0 +1,107 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt,
+};
+
+/// Blocks start on, and are sized in, multiples of 4 bytes.
+const ALIGN: usize = 4;
+
+/// Why a `free` was refused.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FreeError {
+    DoubleFree,
+    NotABlock,
+}
+
+/// Counters reported by `--heap` when the program stops.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct HeapStats {
+    pub allocations: usize,
+    pub frees: usize,
+    pub live_blocks: usize,
+    pub in_use: usize,
+    pub peak: usize,
+}
+
+impl fmt::Display for HeapStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} allocs, {} frees, {} bytes in {} blocks still in use, peak {} bytes",
+            self.allocations, self.frees, self.in_use, self.live_blocks, self.peak
+        )
+    }
+}
+
+/// First-fit allocator over the heap segment. The block list lives on the
+/// host, so a program writing past the end of a block cannot corrupt it.
+#[derive(Debug, Clone)]
+pub struct Heap {
+    start: usize,
+    end: usize,
+    // address -> size of every live block
+    blocks: BTreeMap<usize, usize>,
+    // freed addresses not handed out again since
+    freed: HashSet<usize>,
+    stats: HeapStats,
+}
+
+impl Heap {
+    /// An empty heap over `start..end`.
+    pub fn new(start: usize, end: usize) -> Self {
+        Self {
+            start: start.next_multiple_of(ALIGN),
+            end,
+            blocks: BTreeMap::new(),
+            freed: HashSet::new(),
+            stats: HeapStats::default(),
+        }
+    }
+
+    /// Address of a new block of at least `size` bytes, `None` when no gap
+    /// is large enough. A zero-sized request still gets its own block.
+    pub fn alloc(&mut self, size: usize) -> Option<usize> {
+        let size = size.max(1).checked_next_multiple_of(ALIGN)?;
+        let mut address = self.start;
+        for (&block, &len) in &self.blocks {
+            if block - address >= size {
+                break;
+            }
+            address = block + len;
+        }
+        if address.checked_add(size)? > self.end {
+            return None;
+        }
+
+        self.blocks.insert(address, size);
+        // the new block may cover several old ones, none of them is freed now
+        self.freed
+            .retain(|freed| !(address..address + size).contains(freed));
+        let stats = &mut self.stats;
+        stats.allocations += 1;
+        stats.live_blocks += 1;
+        stats.in_use += size;
+        stats.peak = stats.peak.max(stats.in_use);
+        Some(address)
+    }
+
+    /// Releases the block at `address`.
+    pub fn free(&mut self, address: usize) -> Result<(), FreeError> {
+        let Some(size) = self.blocks.remove(&address) else {
+            return Err(if self.freed.contains(&address) {
+                FreeError::DoubleFree
+            } else {
+                FreeError::NotABlock
+            });
+        };
+        self.freed.insert(address);
+        self.stats.frees += 1;
+        self.stats.live_blocks -= 1;
+        self.stats.in_use -= size;
+        Ok(())
+    }
+
+    pub fn stats(&self) -> HeapStats {
+        self.stats
+    }
+}
@@ -0,0 +1,98 @@
+use std::collections::HashMap;
+
+use crate::{
+    diagnostic::Diagnostic,
+    parser::Stmt,
+    scanner::{Token, TokenType},
+};
+
+/// Registers a function must hand back unchanged, see the calling
+/// convention in the readme.
+const CALLEE_SAVED: [TokenType; 4] = [TokenType::R5, TokenType::R6, TokenType::R7, TokenType::FP];
+
+/// Checks every `call` target against the calling convention. A function
+/// is followed straight down from its label to the first `ret`, so the
+/// lint is a hint rather than a proof.
+pub fn check(statements: &[Stmt], labels: &HashMap<String, usize>) -> Vec<Diagnostic> {
+    let mut functions: Vec<(&String, usize)> = statements
+        .iter()
+        .filter_map(|stmt| match stmt {
+            Stmt::Call { to } => to.literal.as_ref(),
+            _ => None,
+        })
+        .filter_map(|name| labels.get_key_value(name))
+        .map(|(name, index)| (name, *index))
+        .collect();
+    functions.sort();
+    functions.dedup();
+
+    let mut warnings = vec![];
+    for (name, start) in functions {
+        let mut saved = vec![];
+        let mut reported = vec![];
+        let mut open_frame: Option<&Token> = None;
+        for stmt in &statements[start..] {
+            match stmt {
+                Stmt::RET => break,
+                Stmt::PUSH { register_or_imm } => {
+                    saved.push(register_or_imm.token_type);
+                }
+                Stmt::Enter { size } => {
+                    saved.push(TokenType::FP);
+                    open_frame = Some(size);
+                }
+                Stmt::LEAVE => open_frame = None,
+                _ => {}
+            }
+            let Some(reg) = written(stmt) else {
+                continue;
+            };
+            if CALLEE_SAVED.contains(&reg.token_type)
+                && !saved.contains(&reg.token_type)
+                && !reported.contains(&reg.token_type)
+            {
+                reported.push(reg.token_type);
+                let reg_name = format!("{:?}", reg.token_type).to_lowercase();
+                warnings.push(Diagnostic {
+                    message: format!("`{name}` changes callee-saved register {reg_name}"),
+                    line: reg.line_number,
+                    column: reg.column,
+                    hint: format!("push {reg_name} on entry and pop it before `ret`"),
+                });
+            }
+        }
+        if let Some(size) = open_frame {
+            warnings.push(Diagnostic {
+                message: format!("`{name}` returns without `leave`"),
+                line: size.line_number,
+                column: size.column,
+                hint: "this `enter` needs a `leave` before `ret`".to_string(),
+            });
+        }
+    }
+    warnings.sort_by_key(|warning| (warning.line, warning.column));
+    warnings
+}
+
+/// Register a statement writes, if any.
+fn written(stmt: &Stmt) -> Option<&Token> {
+    match stmt {
+        Stmt::MovLit { from, .. } => Some(from),
+        Stmt::ADD { lhs_reg, .. }
+        | Stmt::SUB { lhs_reg, .. }
+        | Stmt::MUL { lhs_reg, .. }
+        | Stmt::DIV { lhs_reg, .. }
+        | Stmt::MOD { lhs_reg, .. } => Some(lhs_reg),
+        Stmt::AndOrXor { reg, .. }
+        | Stmt::Not { reg }
+        | Stmt::IncDecNeg { reg, .. }
+        | Stmt::Shift { reg, .. }
+        | Stmt::POP { reg }
+        | Stmt::Load { reg, .. }
+        | Stmt::Lea { reg, .. }
+        | Stmt::Read { reg, .. }
+        | Stmt::ReadLine { reg, .. }
+        | Stmt::Alloc { reg, .. } => Some(reg),
+        _ => None,
+    }
+}
@@ -1,43 +1,157 @@
 #![allow(dead_code)]
 
-use std::{fs::File, io::Read};
+use std::{
+    error::Error,
+    fs,
+    io::{self, Write},
+    process,
+    time::Instant,
+};
 
-use crate::{backend::CodeGen, parser::Parser, scanner::Scanner};
+use crate::{
+    backend::CodeGen,
+    cli::{Cli, Command, USAGE},
+    debugger::Debugger,
+    disasm::Disassembler,
+    object::{MAGIC, Object},
+    parser::Parser,
+    scanner::Scanner,
+    vm::{Outcome, VM, VmError},
+};
 
 mod backend;
+mod cli;
+mod debugger;
+mod diagnostic;
+mod disasm;
+mod heap;
+mod lint;
+mod object;
 mod parser;
 mod scanner;
+mod syscall;
 mod vm;
 
 fn main() {
-    let mut file = File::open("asm1.mm").unwrap();
-    let mut buff = String::new();
+    let cli = match cli::parse(std::env::args().skip(1)) {
+        Ok(cli) => cli,
+        Err(err) => {
+            eprintln!("error: {err}\n\n{USAGE}");
+            process::exit(2);
+        }
+    };
+    if let Err(err) = run(&cli) {
+        eprintln!("error: {err}");
+        let code = if err.is::<VmError>() { 3 } else { 1 };
+        process::exit(code);
+    }
+}
 
-    file.read_to_string(&mut buff).unwrap();
+fn run(cli: &Cli) -> Result<(), Box<dyn Error>> {
+    match &cli.command {
+        Command::Run { path } => execute(&assemble(path, cli.lint)?, cli),
+        Command::Asm { path, output } => {
+            fs::write(output, assemble(path, cli.lint)?.to_bytes())?;
+            Ok(())
+        }
+        Command::Exec { path } => execute(&Object::from_bytes(&fs::read(path)?)?, cli),
+        Command::Disasm { path } => {
+            let object = Object::from_bytes(&fs::read(path)?)?;
+            print!("{}", Disassembler::new(&object)?.listing()?);
+            Ok(())
+        }
+        Command::Debug { path } => {
+            let bytes = fs::read(path)?;
+            let object = if bytes.starts_with(&MAGIC) {
+                Object::from_bytes(&bytes)?
+            } else {
+                assemble(path, cli.lint)?
+            };
+            let mut debugger = Debugger::new(&object)?;
+            if let Some(path) = &cli.input {
+                debugger.set_input(fs::File::open(path)?);
+            }
+            debugger.run(io::stdin().lock(), io::stdout())?;
+            Ok(())
+        }
+        Command::Help => {
+            println!("{USAGE}");
+            Ok(())
+        }
+    }
+}
 
-    let mut vm = vm::VM::default();
+fn assemble(path: &str, lint: bool) -> Result<Object, Box<dyn Error>> {
+    let buff = fs::read_to_string(path)?;
 
     let mut scanner = Scanner::new(&buff);
-    let p = scanner.parse();
+    let p = scanner.parse()?;
 
     let parser = Parser::new(p.to_vec());
 
-    let mut code_back = CodeGen::new(parser);
-    let code = code_back.gen_();
+    let mut code_back = match CodeGen::new(parser) {
+        Ok(code_back) => code_back,
+        Err(errors) => {
+            for err in &errors {
+                eprintln!("{}", err.diagnostic().render(path, &buff));
+            }
+            let count = match errors.len() {
+                1 => "1 error".to_string(),
+                n => format!("{n} errors"),
+            };
+            return Err(format!("could not assemble `{path}` due to {count}").into());
+        }
+    };
+    if lint {
+        for warning in code_back.lint() {
+            eprintln!("{}", warning.render_warning(path, &buff));
+        }
+    }
+    Ok(code_back.object()?)
+}
 
-    let loop_program = [
-        0x05, 0x00, 0x01, 0x01, // MOV r1,5
-        0x00, 0x00, 0x02, 0x01, // MOV r2,0
-        0x00, 0x00, 0x01, 0x11, // print r1
-        0x01, 0x00, 0x01, 0x05, // SUB r1,1
-        0x00, 0x02, 0x01, 0x06, // CMP r1,r2
-        0x02, 0x00, 0x00, 0x09, // JMPGE
-        0x00, 0x00, 0x00, 0xFF, // HALT
-    ];
+fn execute(object: &Object, cli: &Cli) -> Result<(), Box<dyn Error>> {
+    let mut vm = VM::with_config(cli.config)?;
+    vm.set_budget(cli.max_steps);
+    vm.set_deadline(cli.timeout.map(|timeout| Instant::now() + timeout));
+    vm.load(object)?;
+    if let Some(path) = &cli.input {
+        vm.set_input(fs::File::open(path)?);
+    }
+    let dump = &cli.dump;
+    let result = vm.execute();
 
-    vm.copy(code.0, code.1);
-    vm.execute();
-    println!("<<reg -> {:?}>>", vm.reg);
+    if dump.regs {
+        println!("<<reg -> {:?}>>", vm.reg);
+    }
+    if dump.flags {
+        println!("<<flag -> {}>>", vm.describe_flags());
+    }
+    if dump.heap {
+        println!("<<heap -> {}>>", vm.heap_stats());
+    }
+    for &(start, len) in &dump.memory {
+        let end = start.saturating_add(len).min(vm.memory.len());
+        for (i, row) in vm.memory[start.min(end)..end].chunks(16).enumerate() {
+            println!("{:04x}: {:02x?}", start + i * 16, row);
+        }
+    }
+    let code = match result? {
+        Outcome::Finished(code) => code,
+        Outcome::BudgetExhausted { pc, limit } => {
+            eprintln!(
+                "error: {limit} at pc {pc:#06x} after {} instructions",
+                vm.executed()
+            );
+            4
+        }
+    };
+    if code != 0 {
+        // process::exit skips the destructor that would flush stdout
+        io::stdout().flush()?;
+        process::exit(code);
+    }
+    Ok(())
 }
 
 /*
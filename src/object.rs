@@ -0,0 +1,204 @@
+use std::fmt;
+
+/// On-disk layout of an assembled program, all integers little-endian:
+///
+/// ```text
+/// offset  size  field
+///      0     4  magic "VMMO"
+///      4     2  format version
+///      6     2  ISA version
+///      8     4  entry point (byte address in the code section)
+///     12     4  code size
+///     16     4  data size
+///     20     4  bss size
+///     24     4  symbol count
+///     28     -  code bytes, then data bytes
+///      -     -  symbols: kind u8, width u8, value u32, size u32,
+///                 name length u16, name bytes
+/// ```
+pub const MAGIC: [u8; 4] = *b"VMMO";
+pub const FORMAT_VERSION: u16 = 3;
+/// Bumped whenever the meaning of an existing opcode changes.
+pub const ISA_VERSION: u16 = 2;
+const HEADER_SIZE: usize = 28;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SymbolKind {
+    /// A label, valued by its byte address in the code section.
+    Code,
+    /// A `.data` declaration, valued by its offset in the data section.
+    Data,
+    /// A `.bss` reservation, valued by its offset in the bss section.
+    Bss,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    /// Size in bytes of a data element (1, 2 or 4), 0 for labels.
+    pub width: u8,
+    pub value: u32,
+    /// Length in bytes of a data declaration, 0 for labels.
+    pub size: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Object {
+    pub isa_version: u16,
+    pub entry: u32,
+    pub code: Vec<u8>,
+    pub data: Vec<u8>,
+    pub bss_size: u32,
+    pub symbols: Vec<Symbol>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ObjectError {
+    BadMagic,
+    UnsupportedFormat { version: u16 },
+    UnsupportedIsa { version: u16 },
+    Truncated,
+    TrailingBytes,
+    InvalidSymbol { index: usize },
+}
+
+impl fmt::Display for ObjectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ObjectError::BadMagic => write!(f, "not an object file (bad magic number)"),
+            ObjectError::UnsupportedFormat { version } => write!(
+                f,
+                "unsupported object format version {version}, expected {FORMAT_VERSION}"
+            ),
+            ObjectError::UnsupportedIsa { version } => {
+                write!(
+                    f,
+                    "unsupported ISA version {version}, expected {ISA_VERSION}"
+                )
+            }
+            ObjectError::Truncated => write!(f, "object file is truncated"),
+            ObjectError::TrailingBytes => write!(f, "object file has trailing bytes"),
+            ObjectError::InvalidSymbol { index } => write!(f, "symbol {index} is malformed"),
+        }
+    }
+}
+
+impl std::error::Error for ObjectError {}
+
+impl Object {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER_SIZE + self.code.len() + self.data.len());
+        bytes.extend_from_slice(&MAGIC);
+        bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&self.isa_version.to_le_bytes());
+        bytes.extend_from_slice(&self.entry.to_le_bytes());
+        bytes.extend_from_slice(&(self.code.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&(self.data.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&self.bss_size.to_le_bytes());
+        bytes.extend_from_slice(&(self.symbols.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&self.code);
+        bytes.extend_from_slice(&self.data);
+        for symbol in &self.symbols {
+            let kind = match symbol.kind {
+                SymbolKind::Code => 0u8,
+                SymbolKind::Data => 1u8,
+                SymbolKind::Bss => 2u8,
+            };
+            bytes.push(kind);
+            bytes.push(symbol.width);
+            bytes.extend_from_slice(&symbol.value.to_le_bytes());
+            bytes.extend_from_slice(&symbol.size.to_le_bytes());
+            bytes.extend_from_slice(&(symbol.name.len() as u16).to_le_bytes());
+            bytes.extend_from_slice(symbol.name.as_bytes());
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ObjectError> {
+        let mut reader = Reader { bytes, at: 0 };
+        if reader.take(4)? != MAGIC {
+            return Err(ObjectError::BadMagic);
+        }
+        let format = reader.u16()?;
+        if format != FORMAT_VERSION {
+            return Err(ObjectError::UnsupportedFormat { version: format });
+        }
+        let isa_version = reader.u16()?;
+        if isa_version != ISA_VERSION {
+            return Err(ObjectError::UnsupportedIsa {
+                version: isa_version,
+            });
+        }
+        let entry = reader.u32()?;
+        let code_size = reader.u32()? as usize;
+        let data_size = reader.u32()? as usize;
+        let bss_size = reader.u32()?;
+        let symbol_count = reader.u32()? as usize;
+        let code = reader.take(code_size)?.to_vec();
+        let data = reader.take(data_size)?.to_vec();
+
+        let mut symbols = vec![];
+        for index in 0..symbol_count {
+            let kind = match reader.u8()? {
+                0 => SymbolKind::Code,
+                1 => SymbolKind::Data,
+                2 => SymbolKind::Bss,
+                _ => return Err(ObjectError::InvalidSymbol { index }),
+            };
+            let width = reader.u8()?;
+            let value = reader.u32()?;
+            let size = reader.u32()?;
+            let len = reader.u16()? as usize;
+            let name = String::from_utf8(reader.take(len)?.to_vec())
+                .map_err(|_| ObjectError::InvalidSymbol { index })?;
+            symbols.push(Symbol {
+                name,
+                kind,
+                width,
+                value,
+                size,
+            });
+        }
+        if reader.at != bytes.len() {
+            return Err(ObjectError::TrailingBytes);
+        }
+
+        Ok(Self {
+            isa_version,
+            entry,
+            code,
+            data,
+            bss_size,
+            symbols,
+        })
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    at: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], ObjectError> {
+        let slice = self
+            .bytes
+            .get(self.at..self.at + len)
+            .ok_or(ObjectError::Truncated)?;
+        self.at += len;
+        Ok(slice)
+    }
+
+    fn u8(&mut self) -> Result<u8, ObjectError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, ObjectError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, ObjectError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+}
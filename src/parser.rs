@@ -1,11 +1,50 @@
-use std::{collections::HashMap, panic};
+use std::{collections::HashMap, fmt};
+
+use crate::{
+    diagnostic::Diagnostic,
+    scanner::{Token, TokenType},
+};
+/// Every register operand; `sp` and `fp` follow r0..r7.
+const REGISTERS: &[TokenType] = &[
+    TokenType::R0,
+    TokenType::R1,
+    TokenType::R2,
+    TokenType::R3,
+    TokenType::R4,
+    TokenType::R5,
+    TokenType::R6,
+    TokenType::R7,
+    TokenType::SP,
+    TokenType::FP,
+];
+const REGISTER_OR_INT: &[TokenType] = &registers_and::<11>(&[TokenType::INT]);
+/// The base of a memory operand, a register or a label.
+const REGISTER_OR_IDENT: &[TokenType] = &registers_and::<11>(&[TokenType::IDENT]);
+/// The source of `mov` and `cmp`, which also take a data symbol.
+const REGISTER_INT_OR_IDENT: &[TokenType] =
+    &registers_and::<12>(&[TokenType::INT, TokenType::IDENT]);
+
+/// [`REGISTERS`] followed by `extra`; `N` must be their combined length.
+const fn registers_and<const N: usize>(extra: &[TokenType]) -> [TokenType; N] {
+    assert!(N == REGISTERS.len() + extra.len());
+    let mut all = [TokenType::EOF; N];
+    let mut i = 0;
+    while i < N {
+        all[i] = if i < REGISTERS.len() {
+            REGISTERS[i]
+        } else {
+            extra[i - REGISTERS.len()]
+        };
+        i += 1;
+    }
+    all
+}
 
-use crate::scanner::{Token, TokenType};
 macro_rules! INSERT {
     ($s:expr,$name:ident) => {
-        let reg = $s.consume_2(&[R0, R1, R2, R3, R4, R5, R6, R7]);
-        $s.consume(Comma);
-        let register_or_imm = $s.consume_2(&[R0, R1, R2, R3, R4, R5, R6, R7, INT]);
+        let reg = $s.register()?;
+        $s.consume(TokenType::Comma)?;
+        let register_or_imm = $s.register_or_int()?;
         $s.statements.push(Stmt::$name {
             lhs_reg: reg,
             right_reg_imm: register_or_imm,
@@ -18,7 +57,7 @@ macro_rules! INSERT {
 pub enum Stmt {
     MovLit {
         from: Token,
-        register_or_imm_IDENT: Token,
+        register_or_imm_ident: Token,
     },
     Halt {
         token: Token,
@@ -45,10 +84,70 @@ pub enum Stmt {
     JMP {
         to: Token,
     },
+    JMPNZ {
+        to: Token,
+    },
+    JC {
+        to: Token,
+    },
+    JNC {
+        to: Token,
+    },
+    JO {
+        to: Token,
+    },
+    JA {
+        to: Token,
+    },
+    JB {
+        to: Token,
+    },
+    JAE {
+        to: Token,
+    },
+    JBE {
+        to: Token,
+    },
+    JE {
+        to: Token,
+    },
+    JNE {
+        to: Token,
+    },
+    JEOF {
+        to: Token,
+    },
     Call {
         to: Token,
     },
     RET,
+    PUSHF,
+    POPF,
+    Enter {
+        size: Token,
+    },
+    LEAVE,
+    IRET,
+    Interrupt {
+        number: Token,
+    },
+    SYSCALL,
+    /// `read rN` or `readc rN`
+    Read {
+        type_op: TokenType,
+        reg: Token,
+    },
+    ReadLine {
+        reg: Token,
+        address: Address,
+    },
+    Alloc {
+        reg: Token,
+        register_or_imm: Token,
+    },
+    Free {
+        reg: Token,
+    },
     ADD {
         lhs_reg: Token,
         right_reg_imm: Token,
@@ -75,42 +174,224 @@ pub enum Stmt {
     Print {
         reg: Token,
     },
+    PrintChar {
+        reg: Token,
+    },
+    PrintString {
+        address: Address,
+    },
     PUSH {
         register_or_imm: Token,
     },
     POP {
         reg: Token,
     },
-    AND_OR_XOR {
+    AndOrXor {
         type_op: TokenType,
         reg: Token,
         register_or_imm: Token,
     },
+    Not {
+        reg: Token,
+    },
+    IncDecNeg {
+        type_op: TokenType,
+        reg: Token,
+    },
+    Test {
+        reg: Token,
+        register_or_imm: Token,
+    },
+    Shift {
+        type_op: TokenType,
+        reg: Token,
+        register_or_imm: Token,
+    },
+    Load {
+        type_op: TokenType,
+        reg: Token,
+        address: Address,
+    },
+    Store {
+        type_op: TokenType,
+        reg: Token,
+        address: Address,
+    },
+    Lea {
+        reg: Token,
+        address: Address,
+    },
+}
+
+/// A bracketed memory operand.
+#[derive(Debug, Clone)]
+pub enum Address {
+    /// `[label]`
+    Label(Token),
+    /// `[rN]`, `[rN + imm]` or `[rN - imm]`
+    Register { base: Token, offset: i32 },
+}
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseError {
+    UnexpectedToken {
+        expected: Vec<TokenType>,
+        found: TokenType,
+        line: usize,
+        column: usize,
+    },
+    ExpectedInstruction {
+        found: TokenType,
+        line: usize,
+        column: usize,
+    },
+    /// Nothing left on the line for an operand, reported at its end.
+    MissingOperand {
+        expected: Vec<TokenType>,
+        line: usize,
+        column: usize,
+    },
+    InvalidNumber {
+        literal: String,
+        line: usize,
+        column: usize,
+    },
+}
+
+impl ParseError {
+    pub fn diagnostic(&self) -> Diagnostic {
+        match self {
+            ParseError::UnexpectedToken {
+                expected,
+                found,
+                line,
+                column,
+            } => Diagnostic {
+                message: format!("unexpected token {found:?}"),
+                line: *line,
+                column: *column,
+                hint: format!("expected {}, found {found:?}", describe(expected)),
+            },
+            ParseError::ExpectedInstruction {
+                found,
+                line,
+                column,
+            } => Diagnostic {
+                message: format!("expected an instruction, found {found:?}"),
+                line: *line,
+                column: *column,
+                hint: "statements start with a mnemonic such as `mov` or a `label:`".to_string(),
+            },
+            ParseError::MissingOperand {
+                expected,
+                line,
+                column,
+            } => Diagnostic {
+                message: "missing operand".to_string(),
+                line: *line,
+                column: *column,
+                hint: format!("expected {} before the end of the line", describe(expected)),
+            },
+            ParseError::InvalidNumber {
+                literal,
+                line,
+                column,
+            } => Diagnostic {
+                message: format!("invalid number `{literal}`"),
+                line: *line,
+                column: *column,
+                hint: "number is malformed or does not fit in the declared width".to_string(),
+            },
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let diagnostic = self.diagnostic();
+        write!(
+            f,
+            "{} at {}:{}",
+            diagnostic.message, diagnostic.line, diagnostic.column
+        )
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Human readable form of an expected token set, e.g. "register r0..r7, sp, fp or integer".
+fn describe(expected: &[TokenType]) -> String {
+    use TokenType::*;
+    let mut parts = vec![];
+    if REGISTERS.iter().all(|reg| expected.contains(reg)) {
+        parts.push("register r0..r7, sp, fp".to_string());
+    }
+    for token in expected {
+        match token {
+            reg if REGISTERS.contains(reg) => {}
+            INT => parts.push("integer".to_string()),
+            STRING => parts.push("string".to_string()),
+            IDENT => parts.push("identifier".to_string()),
+            Comma => parts.push("`,`".to_string()),
+            LBracket => parts.push("`[`".to_string()),
+            RBracket => parts.push("`]`".to_string()),
+            other => parts.push(format!("`{}`", format!("{other:?}").to_lowercase())),
+        }
+    }
+    match parts.split_last() {
+        Some((last, [])) => last.clone(),
+        Some((last, rest)) => format!("{} or {last}", rest.join(", ")),
+        None => "nothing".to_string(),
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(token: Token) -> Result<T, ParseError> {
+    let literal = token.literal.unwrap_or_default();
+    literal.parse::<T>().map_err(|_| ParseError::InvalidNumber {
+        literal,
+        line: token.line_number,
+        column: token.column,
+    })
 }
+
+/// A `.bss` reservation of `count` elements of `width` bytes.
+#[derive(Clone)]
+pub struct Reserve {
+    pub width: u8,
+    pub count: u16,
+    pub name: Token,
+}
+
 #[derive(Clone)]
 pub enum Data {
-    DB(i8, Token),
-    DW(i16, Token),
-    DD(i32, Token),
+    DB(Vec<i8>, Token),
+    DW(Vec<i16>, Token),
+    DD(Vec<i32>, Token),
 }
 pub struct Parser {
     tokens: Vec<Token>,
     statements: Vec<Stmt>,
     data: Vec<Data>,
+    bss: Vec<Reserve>,
     current: usize,
     mapping_table: HashMap<String, usize>,
+    errors: Vec<ParseError>,
 }
 impl Parser {
     pub fn get_table(&self) -> &HashMap<String, usize> {
         &self.mapping_table
     }
+    pub fn get_bss(&self) -> &[Reserve] {
+        &self.bss
+    }
     pub fn new(tokens: Vec<Token>) -> Self {
         Self {
             tokens,
             statements: vec![],
             data: vec![],
+            bss: vec![],
             current: 0,
             mapping_table: HashMap::new(),
+            errors: vec![],
         }
     }
 
@@ -129,38 +410,134 @@ impl Parser {
         self.peek().token_type == TokenType::EOF
     }
 
-    pub fn parse(&mut self) -> (&[Stmt], &[Data]) {
+    /// Parses the whole program, collecting every error instead of stopping
+    /// at the first one.
+    pub fn parse(&mut self) -> Result<(&[Stmt], &[Data]), Vec<ParseError>> {
         if self.match_(&[TokenType::DATA]) {
             self.data();
         }
+        if self.match_(&[TokenType::BSS]) {
+            self.bss();
+        }
         if self.match_(&[TokenType::CODE]) {
             self.statements();
         }
+        if !self.is_end()
+            && let Err(err) = self.leading(&[TokenType::DATA, TokenType::BSS, TokenType::CODE])
+        {
+            self.errors.push(err);
+        }
 
-        (&self.statements, &self.data)
+        if self.errors.is_empty() {
+            Ok((&self.statements, &self.data))
+        } else {
+            Err(self.errors.clone())
+        }
     }
+
+    /// Skips the rest of a broken statement: resumes at the next line or at
+    /// the next token that can begin a statement, whichever comes first.
+    fn synchronize(&mut self, start: usize) {
+        if self.current == start {
+            self.current += 1;
+        }
+        let line = self.tokens[start].line_number;
+        while !self.is_end() {
+            let token = self.peek();
+            if token.line_number != line || token.token_type.starts_statement() {
+                break;
+            }
+            self.current += 1;
+        }
+    }
+
     pub fn data(&mut self) {
+        while !self.is_end() {
+            if matches!(self.peek().token_type, TokenType::BSS | TokenType::CODE) {
+                return;
+            }
+            let start = self.current;
+            if let Err(err) = self.declaration() {
+                self.errors.push(err);
+                self.synchronize(start);
+            }
+        }
+    }
+
+    pub fn bss(&mut self) {
         while !self.is_end() {
             if self.peek().token_type == TokenType::CODE {
                 return;
             }
-            let token = self.peek().token_type;
-            if token == TokenType::DB {
-                self.advance();
-                self.db();
+            let start = self.current;
+            if let Err(err) = self.reservation() {
+                self.errors.push(err);
+                self.synchronize(start);
+            }
+        }
+    }
+
+    fn reservation(&mut self) -> Result<(), ParseError> {
+        use TokenType::*;
+        let directive = self.leading(&[RESB, RESW, RESD])?;
+        let name = self.consume_2(&[IDENT])?;
+        let count = self.number::<u16>()?;
+        let width = match directive.token_type {
+            RESB => 1,
+            RESW => 2,
+            _ => 4,
+        };
+        self.bss.push(Reserve { width, count, name });
+        Ok(())
+    }
+
+    fn declaration(&mut self) -> Result<(), ParseError> {
+        let directive = self.leading(&[TokenType::DB, TokenType::DW, TokenType::DD])?;
+        let ident = self.consume_2(&[TokenType::IDENT])?;
+        let data = match directive.token_type {
+            TokenType::DB => Data::DB(self.values(Some(|byte| byte as i8))?, ident),
+            TokenType::DW => Data::DW(self.values(None)?, ident),
+            _ => Data::DD(self.values(None)?, ident),
+        };
+        self.data.push(data);
+        Ok(())
+    }
+
+    /// A comma separated list of numbers, `times N value` repetitions and,
+    /// when `from_byte` is given, string literals.
+    fn values<T: std::str::FromStr + Clone>(
+        &mut self,
+        from_byte: Option<fn(u8) -> T>,
+    ) -> Result<Vec<T>, ParseError> {
+        use TokenType::*;
+        let expected: &[TokenType] = match from_byte {
+            Some(_) => &[INT, STRING, TIMES],
+            None => &[INT, TIMES],
+        };
+        let mut values = vec![];
+        loop {
+            let token = self.consume_2(expected)?;
+            match (token.token_type, from_byte) {
+                (TIMES, _) => {
+                    let count = self.number::<u16>()?;
+                    let value = self.number::<T>()?;
+                    values.extend(std::iter::repeat_n(value, count as usize));
+                }
+                (STRING, Some(from_byte)) => {
+                    let string = token.literal.unwrap_or_default();
+                    values.extend(string.bytes().map(from_byte));
+                }
+                _ => values.push(parse_number(token)?),
+            }
+            if !self.match_(&[TokenType::Comma]) {
+                return Ok(values);
             }
         }
     }
 
-    pub fn db(&mut self) {
-        let ident = self.consume_2(&[TokenType::IDENT]);
-        let num = self
-            .consume_2(&[TokenType::INT])
-            .literal
-            .unwrap()
-            .parse::<i8>()
-            .unwrap();
-        self.data.push(Data::DB(num, ident));
+    fn number<T: std::str::FromStr>(&mut self) -> Result<T, ParseError> {
+        let token = self.consume_2(&[TokenType::INT])?;
+        parse_number(token)
     }
 
     pub fn match_(&mut self, token: &[TokenType]) -> bool {
@@ -174,148 +551,364 @@ impl Parser {
     }
     pub fn statements(&mut self) {
         while !self.is_end() {
-            if self.match_(&[TokenType::MOV]) {
-                self.mov_statement();
-            } else if self.match_(&[TokenType::HALT]) {
-                self.halt();
-            } else if self.match_(&[TokenType::CMP]) {
-                self.compare_stmt();
-            } else if self.match_(&[TokenType::JMPG]) {
-                self.jump_stmt();
-            } else if self.match_(&[TokenType::ADD]) {
-                use TokenType::*;
-                INSERT!(self, ADD);
-            } else if self.match_(&[TokenType::SUB]) {
-                use TokenType::*;
-                INSERT!(self, SUB);
-            } else if self.match_(&[TokenType::MUL]) {
-                use TokenType::*;
-                INSERT!(self, MUL);
-            } else if self.match_(&[TokenType::DIV]) {
-                use TokenType::*;
-                INSERT!(self, DIV);
-            } else if self.match_(&[TokenType::MOD]) {
-                use TokenType::*;
-                INSERT!(self, MOD);
-            } else if self.match_(&[TokenType::JUMP]) {
-                let token = self.consume_2(&[TokenType::IDENT]);
-                self.statements.push(Stmt::JMP { to: token });
-            } else if self.match_(&[TokenType::Print]) {
-                self.print_st();
-            } else if self.match_(&[TokenType::LabelDef]) {
-                self.label_def();
-            } else if self.match_(&[TokenType::Call]) {
-                self.call();
-            } else if self.match_(&[TokenType::Ret]) {
-                self.statements.push(Stmt::RET);
-            } else if self.match_(&[TokenType::JMPL]) {
-                self.jump_stmt_2();
-            } else if self.match_(&[TokenType::PUSH]) {
-                self.push();
-            } else if self.match_(&[TokenType::POP]) {
-                self.pop();
-            } else if self.match_(&[TokenType::AND, TokenType::OR, TokenType::XOR]) {
-                self.bit_wise();
-            } else if self.match_(&[TokenType::JMPZ]) {
-                let token = self.consume_2(&[TokenType::IDENT]);
-                self.statements.push(Stmt::JMPZ { to: token });
-            } else if self.match_(&[TokenType::JMPLE]) {
-                let token = self.consume_2(&[TokenType::IDENT]);
-                self.statements.push(Stmt::JMPLE { to: token });
-            } else if self.match_(&[TokenType::JMPGE]) {
-                let token = self.consume_2(&[TokenType::IDENT]);
-                self.statements.push(Stmt::JMPGE { to: token });
-            } else {
-                panic!("uknown token {:?}", self.peek());
+            let start = self.current;
+            if let Err(err) = self.statement() {
+                self.errors.push(err);
+                self.synchronize(start);
             }
         }
     }
-    fn bit_wise(&mut self) {
-        use TokenType::*;
+
+    fn statement(&mut self) -> Result<(), ParseError> {
+        if self.match_(&[TokenType::MOV]) {
+            self.mov_statement()?;
+        } else if self.match_(&[TokenType::HALT]) {
+            self.halt();
+        } else if self.match_(&[TokenType::NOP]) {
+            self.statements.push(Stmt::NOP);
+        } else if self.match_(&[TokenType::CMP]) {
+            self.compare_stmt()?;
+        } else if self.match_(&[TokenType::JMPG]) {
+            self.jump_stmt()?;
+        } else if self.match_(&[TokenType::ADD]) {
+            INSERT!(self, ADD);
+        } else if self.match_(&[TokenType::SUB]) {
+            INSERT!(self, SUB);
+        } else if self.match_(&[TokenType::MUL]) {
+            INSERT!(self, MUL);
+        } else if self.match_(&[TokenType::DIV]) {
+            INSERT!(self, DIV);
+        } else if self.match_(&[TokenType::MOD]) {
+            INSERT!(self, MOD);
+        } else if self.match_(&[TokenType::JUMP]) {
+            let token = self.consume_2(&[TokenType::IDENT])?;
+            self.statements.push(Stmt::JMP { to: token });
+        } else if self.match_(&[TokenType::Print]) {
+            self.print_st()?;
+        } else if self.match_(&[TokenType::PRINTC]) {
+            let reg = self.register()?;
+            self.statements.push(Stmt::PrintChar { reg });
+        } else if self.match_(&[TokenType::PRINTS]) {
+            let address = self.address()?;
+            self.statements.push(Stmt::PrintString { address });
+        } else if self.match_(&[TokenType::LabelDef]) {
+            self.label_def();
+        } else if self.match_(&[TokenType::Call]) {
+            self.call()?;
+        } else if self.match_(&[TokenType::Ret]) {
+            self.statements.push(Stmt::RET);
+        } else if self.match_(&[TokenType::PUSHF]) {
+            self.statements.push(Stmt::PUSHF);
+        } else if self.match_(&[TokenType::POPF]) {
+            self.statements.push(Stmt::POPF);
+        } else if self.match_(&[TokenType::ENTER]) {
+            let size = self.consume_2(&[TokenType::INT])?;
+            self.statements.push(Stmt::Enter { size });
+        } else if self.match_(&[TokenType::LEAVE]) {
+            self.statements.push(Stmt::LEAVE);
+        } else if self.match_(&[TokenType::IRET]) {
+            self.statements.push(Stmt::IRET);
+        } else if self.match_(&[TokenType::Interrupt]) {
+            let number = self.consume_2(&[TokenType::INT])?;
+            self.statements.push(Stmt::Interrupt { number });
+        } else if self.match_(&[TokenType::SYSCALL]) {
+            self.statements.push(Stmt::SYSCALL);
+        } else if self.match_(&[TokenType::JMPL]) {
+            self.jump_stmt_2()?;
+        } else if self.match_(&[TokenType::PUSH]) {
+            self.push()?;
+        } else if self.match_(&[TokenType::POP]) {
+            self.pop()?;
+        } else if self.match_(&[TokenType::AND, TokenType::OR, TokenType::XOR]) {
+            self.bit_wise()?;
+        } else if self.match_(&[TokenType::NOT]) {
+            let reg = self.register()?;
+            self.statements.push(Stmt::Not { reg });
+        } else if self.match_(&[TokenType::INC, TokenType::DEC, TokenType::NEG]) {
+            let type_op = self.previous().token_type;
+            let reg = self.register()?;
+            self.statements.push(Stmt::IncDecNeg { type_op, reg });
+        } else if self.match_(&[TokenType::TEST]) {
+            let reg = self.register()?;
+            self.consume(TokenType::Comma)?;
+            let register_or_imm = self.register_or_int()?;
+            self.statements.push(Stmt::Test {
+                reg,
+                register_or_imm,
+            });
+        } else if self.match_(&[
+            TokenType::SHL,
+            TokenType::SHR,
+            TokenType::SAR,
+            TokenType::ROL,
+            TokenType::ROR,
+        ]) {
+            self.shift()?;
+        } else if self.match_(&[TokenType::JMPZ]) {
+            let token = self.consume_2(&[TokenType::IDENT])?;
+            self.statements.push(Stmt::JMPZ { to: token });
+        } else if self.match_(&[TokenType::JMPLE]) {
+            let token = self.consume_2(&[TokenType::IDENT])?;
+            self.statements.push(Stmt::JMPLE { to: token });
+        } else if self.match_(&[TokenType::JMPGE]) {
+            let token = self.consume_2(&[TokenType::IDENT])?;
+            self.statements.push(Stmt::JMPGE { to: token });
+        } else if self.match_(&[TokenType::JMPNZ]) {
+            let token = self.consume_2(&[TokenType::IDENT])?;
+            self.statements.push(Stmt::JMPNZ { to: token });
+        } else if self.match_(&[TokenType::JC]) {
+            let token = self.consume_2(&[TokenType::IDENT])?;
+            self.statements.push(Stmt::JC { to: token });
+        } else if self.match_(&[TokenType::JNC]) {
+            let token = self.consume_2(&[TokenType::IDENT])?;
+            self.statements.push(Stmt::JNC { to: token });
+        } else if self.match_(&[TokenType::JO]) {
+            let token = self.consume_2(&[TokenType::IDENT])?;
+            self.statements.push(Stmt::JO { to: token });
+        } else if self.match_(&[TokenType::JA]) {
+            let token = self.consume_2(&[TokenType::IDENT])?;
+            self.statements.push(Stmt::JA { to: token });
+        } else if self.match_(&[TokenType::JB]) {
+            let token = self.consume_2(&[TokenType::IDENT])?;
+            self.statements.push(Stmt::JB { to: token });
+        } else if self.match_(&[TokenType::JAE]) {
+            let token = self.consume_2(&[TokenType::IDENT])?;
+            self.statements.push(Stmt::JAE { to: token });
+        } else if self.match_(&[TokenType::JBE]) {
+            let token = self.consume_2(&[TokenType::IDENT])?;
+            self.statements.push(Stmt::JBE { to: token });
+        } else if self.match_(&[TokenType::JE]) {
+            let token = self.consume_2(&[TokenType::IDENT])?;
+            self.statements.push(Stmt::JE { to: token });
+        } else if self.match_(&[TokenType::JNE]) {
+            let token = self.consume_2(&[TokenType::IDENT])?;
+            self.statements.push(Stmt::JNE { to: token });
+        } else if self.match_(&[TokenType::JEOF]) {
+            let token = self.consume_2(&[TokenType::IDENT])?;
+            self.statements.push(Stmt::JEOF { to: token });
+        } else if self.match_(&[TokenType::LOADB, TokenType::LOADW, TokenType::LOADD]) {
+            self.load()?;
+        } else if self.match_(&[TokenType::STOREB, TokenType::STOREW, TokenType::STORED]) {
+            self.store()?;
+        } else if self.match_(&[TokenType::LEA]) {
+            let reg = self.register()?;
+            self.consume(TokenType::Comma)?;
+            let address = self.address()?;
+            self.statements.push(Stmt::Lea { reg, address });
+        } else if self.match_(&[TokenType::READ, TokenType::READC]) {
+            let type_op = self.previous().token_type;
+            let reg = self.register()?;
+            self.statements.push(Stmt::Read { type_op, reg });
+        } else if self.match_(&[TokenType::READLN]) {
+            let reg = self.register()?;
+            self.consume(TokenType::Comma)?;
+            let address = self.address()?;
+            self.statements.push(Stmt::ReadLine { reg, address });
+        } else if self.match_(&[TokenType::ALLOC]) {
+            let reg = self.register()?;
+            self.consume(TokenType::Comma)?;
+            let register_or_imm = self.register_or_int()?;
+            self.statements.push(Stmt::Alloc {
+                reg,
+                register_or_imm,
+            });
+        } else if self.match_(&[TokenType::FREE]) {
+            let reg = self.register()?;
+            self.statements.push(Stmt::Free { reg });
+        } else {
+            let token = self.peek();
+            return Err(ParseError::ExpectedInstruction {
+                found: token.token_type,
+                line: token.line_number,
+                column: token.column,
+            });
+        }
+        Ok(())
+    }
+    fn bit_wise(&mut self) -> Result<(), ParseError> {
         let type_op = self.previous().token_type;
-        let register = self.consume_2(&[R0, R1, R2, R3, R4, R5, R6, R7]);
-        self.consume(Comma);
-        let register_or_imm = self.consume_2(&[R0, R1, R2, R3, R4, R5, R6, R7, INT]);
-        self.statements.push(Stmt::AND_OR_XOR {
+        let register = self.register()?;
+        self.consume(TokenType::Comma)?;
+        let register_or_imm = self.register_or_int()?;
+        self.statements.push(Stmt::AndOrXor {
             type_op,
             reg: register,
             register_or_imm,
         });
+        Ok(())
     }
 
-    pub fn pop(&mut self) {
-        use TokenType::*;
-        let register_or_imm = self.consume_2(&[R0, R1, R2, R3, R4, R5, R6, R7]);
+    fn shift(&mut self) -> Result<(), ParseError> {
+        let type_op = self.previous().token_type;
+        let reg = self.register()?;
+        self.consume(TokenType::Comma)?;
+        let register_or_imm = self.register_or_int()?;
+        self.statements.push(Stmt::Shift {
+            type_op,
+            reg,
+            register_or_imm,
+        });
+        Ok(())
+    }
+
+    pub fn pop(&mut self) -> Result<(), ParseError> {
+        let register_or_imm = self.register()?;
         self.statements.push(Stmt::POP {
             reg: register_or_imm,
         });
+        Ok(())
     }
-    pub fn push(&mut self) {
-        use TokenType::*;
-        let register_or_imm = self.consume_2(&[R0, R1, R2, R3, R4, R5, R6, R7, INT]);
+    pub fn push(&mut self) -> Result<(), ParseError> {
+        let register_or_imm = self.register_or_int()?;
         self.statements.push(Stmt::PUSH { register_or_imm });
+        Ok(())
     }
 
-    pub fn call(&mut self) {
-        let int_token = self.consume_2(&[TokenType::IDENT]);
+    pub fn call(&mut self) -> Result<(), ParseError> {
+        let int_token = self.consume_2(&[TokenType::IDENT])?;
         self.statements.push(Stmt::Call { to: int_token });
+        Ok(())
     }
 
     pub fn label_def(&mut self) {
+        // a label names the statement that follows it, it emits no code
         let token = self.previous();
-        self.statements.push(Stmt::NOP);
-        let index = self.statements.len() - 1;
+        let index = self.statements.len();
         self.mapping_table.insert(token.literal.unwrap(), index);
     }
 
-    pub fn print_st(&mut self) {
-        use TokenType::*;
-        let reg = self.consume_2(&[R0, R1, R2, R3, R4, R5, R6, R7]);
+    pub fn print_st(&mut self) -> Result<(), ParseError> {
+        let reg = self.register()?;
         self.statements.push(Stmt::Print { reg });
+        Ok(())
     }
-    pub fn jump_stmt(&mut self) {
-        let token = self.consume_2(&[TokenType::IDENT]);
+    pub fn jump_stmt(&mut self) -> Result<(), ParseError> {
+        let token = self.consume_2(&[TokenType::IDENT])?;
         self.statements.push(Stmt::JMPG { to: token });
+        Ok(())
     }
-    pub fn jump_stmt_2(&mut self) {
-        let token = self.consume_2(&[TokenType::IDENT]);
+    pub fn jump_stmt_2(&mut self) -> Result<(), ParseError> {
+        let token = self.consume_2(&[TokenType::IDENT])?;
         self.statements.push(Stmt::JMPL { to: token });
+        Ok(())
     }
 
-    pub fn compare_stmt(&mut self) {
+    fn load(&mut self) -> Result<(), ParseError> {
+        let type_op = self.previous().token_type;
+        let reg = self.register()?;
+        self.consume(TokenType::Comma)?;
+        let address = self.address()?;
+        self.statements.push(Stmt::Load {
+            type_op,
+            reg,
+            address,
+        });
+        Ok(())
+    }
+
+    fn store(&mut self) -> Result<(), ParseError> {
+        let type_op = self.previous().token_type;
+        let reg = self.register()?;
+        self.consume(TokenType::Comma)?;
+        let address = self.address()?;
+        self.statements.push(Stmt::Store {
+            type_op,
+            reg,
+            address,
+        });
+        Ok(())
+    }
+
+    fn address(&mut self) -> Result<Address, ParseError> {
         use TokenType::*;
-        let reg = self.consume_2(&[R0, R1, R2, R3, R4, R5, R6, R7]);
-        self.consume(Comma);
-        let register_or_imm = self.consume_2(&[R0, R1, R2, R3, R4, R5, R6, R7, INT,IDENT]);
+        self.consume(LBracket)?;
+        let base = self.consume_2(REGISTER_OR_IDENT)?;
+        let address = if base.token_type == IDENT {
+            Address::Label(base)
+        } else {
+            let offset = if self.match_(&[Plus]) {
+                self.number::<i32>()?
+            } else if self.match_(&[Minus]) {
+                let token = self.peek().clone();
+                self.number::<i32>()?
+                    .checked_neg()
+                    .ok_or(ParseError::InvalidNumber {
+                        literal: format!("-{}", token.literal.unwrap_or_default()),
+                        line: token.line_number,
+                        column: token.column,
+                    })?
+            } else if self.peek().token_type == INT
+                && self
+                    .peek()
+                    .literal
+                    .as_ref()
+                    .is_some_and(|l| l.starts_with('-'))
+            {
+                // `[r1 -4]`, the scanner glued the sign to the number
+                self.number::<i32>()?
+            } else {
+                0
+            };
+            Address::Register { base, offset }
+        };
+        self.consume(RBracket)?;
+        Ok(address)
+    }
+
+    pub fn compare_stmt(&mut self) -> Result<(), ParseError> {
+        let reg = self.register()?;
+        self.consume(TokenType::Comma)?;
+        let register_or_imm = self.consume_2(REGISTER_INT_OR_IDENT)?;
         self.statements.push(Stmt::CMP {
             from_reg: reg,
             register_or_imm,
         });
+        Ok(())
     }
 
-    fn consume(&mut self, expected: TokenType) {
-        let prev = self.previous();
-        if self.peek().token_type == expected {
-            self.current += 1;
-        } else {
-            panic!(
-                "expeted {:?} at line {} after {:?}",
-                expected,
-                self.peek().line_number - 1,
-                prev.token_type
-            );
+    fn consume(&mut self, expected: TokenType) -> Result<(), ParseError> {
+        self.consume_2(&[expected]).map(|_| ())
+    }
+
+    /// Consumes an operand, which must be on the line of the token before
+    /// it. One on a later line is missing and reported at the end of the
+    /// line, not at the start of the next.
+    fn consume_2(&mut self, expected: &[TokenType]) -> Result<Token, ParseError> {
+        if let Some(prev) = self.current.checked_sub(1).map(|i| &self.tokens[i])
+            && prev.line_number < self.peek().line_number
+        {
+            return Err(ParseError::MissingOperand {
+                expected: expected.to_vec(),
+                line: prev.line_number,
+                column: prev.end,
+            });
         }
+        self.leading(expected)
     }
 
-    fn consume_2(&mut self, expected: &[TokenType]) -> Token {
+    /// Consumes the token that begins a line, e.g. a directive.
+    fn leading(&mut self, expected: &[TokenType]) -> Result<Token, ParseError> {
         let tk = self.peek().clone();
         for i in expected {
             if tk.token_type == *i {
                 self.current += 1;
-                return tk;
+                return Ok(tk);
             }
         }
-        panic!("Expected {:?}, found {:?}", expected, tk);
+        Err(ParseError::UnexpectedToken {
+            expected: expected.to_vec(),
+            found: tk.token_type,
+            line: tk.line_number,
+            column: tk.column,
+        })
+    }
+
+    fn register(&mut self) -> Result<Token, ParseError> {
+        self.consume_2(REGISTERS)
+    }
+
+    fn register_or_int(&mut self) -> Result<Token, ParseError> {
+        self.consume_2(REGISTER_OR_INT)
     }
 
     fn previous(&self) -> Token {
@@ -326,14 +919,14 @@ impl Parser {
         let token = self.previous();
         self.statements.push(Stmt::Halt { token });
     }
-    fn mov_statement(&mut self) {
-        use TokenType::*;
-        let register = self.consume_2(&[R0, R1, R2, R3, R4, R5, R6, R7]);
-        self.consume(Comma);
-        let register_or_imm_IDENT = self.consume_2(&[R0, R1, R2, R3, R4, R5, R6, R7, INT, IDENT]);
+    fn mov_statement(&mut self) -> Result<(), ParseError> {
+        let register = self.register()?;
+        self.consume(TokenType::Comma)?;
+        let register_or_imm_ident = self.consume_2(REGISTER_INT_OR_IDENT)?;
         self.statements.push(Stmt::MovLit {
             from: register,
-            register_or_imm_IDENT,
+            register_or_imm_ident,
         });
+        Ok(())
     }
 }
@@ -1,10 +1,12 @@
-use std::{collections::HashMap, iter::Peekable, str::Chars};
+use std::{collections::HashMap, fmt, iter::Peekable, str::Chars};
 
 #[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum TokenType {
     MOV,
     Print,
+    PRINTC,
+    PRINTS,
     R0,
     R1,
     R2,
@@ -13,6 +15,8 @@ pub enum TokenType {
     R5,
     R6,
     R7,
+    SP,
+    FP,
     MUL,
     DIV,
     ADD,
@@ -24,12 +28,35 @@ pub enum TokenType {
     JMPLE,
     JMPZ,
     JMPNZ,
+    JC,
+    JNC,
+    JO,
+    JA,
+    JB,
+    JAE,
+    JBE,
+    JE,
+    JNE,
+    JEOF,
     JUMP,
     INT,
     PUSH,
+    PUSHF,
+    POPF,
+    ENTER,
+    LEAVE,
+    IRET,
+    Interrupt,
+    SYSCALL,
+    READ,
+    READC,
+    READLN,
+    ALLOC,
+    FREE,
     POP,
     Comma,
     HALT,
+    NOP,
     EOF,
     LabelDef,
     IDENT,
@@ -38,39 +65,137 @@ pub enum TokenType {
     OR,
     XOR,
     NOT,
+    INC,
+    DEC,
+    NEG,
+    TEST,
+    SHL,
+    SHR,
+    SAR,
+    ROL,
+    ROR,
     Ret,
     MOD,
     DATA,
+    BSS,
     CODE,
     DW,
     DB,
     DD,
+    STRING,
+    TIMES,
+    RESB,
+    RESW,
+    RESD,
+    LOADB,
+    LOADW,
+    LOADD,
+    STOREB,
+    STOREW,
+    STORED,
+    LEA,
+    LBracket,
+    RBracket,
+    Plus,
+    Minus,
 }
 impl TokenType {
-    pub fn get_reg(&self) -> (u32, TokenType) {
+    pub fn get_reg(&self) -> Option<(u32, TokenType)> {
         match self {
-            TokenType::R0 => (0, TokenType::R0),
-            TokenType::R1 => (1, TokenType::R1),
-            TokenType::R2 => (2, TokenType::R2),
-            TokenType::R3 => (3, TokenType::R3),
-            TokenType::R4 => (4, TokenType::R4),
-            TokenType::R5 => (5, TokenType::R5),
-            TokenType::R6 => (6, TokenType::R6),
-            TokenType::R7 => (7, TokenType::R7),
-            _ => panic!("Not a register"),
+            TokenType::R0 => Some((0, TokenType::R0)),
+            TokenType::R1 => Some((1, TokenType::R1)),
+            TokenType::R2 => Some((2, TokenType::R2)),
+            TokenType::R3 => Some((3, TokenType::R3)),
+            TokenType::R4 => Some((4, TokenType::R4)),
+            TokenType::R5 => Some((5, TokenType::R5)),
+            TokenType::R6 => Some((6, TokenType::R6)),
+            TokenType::R7 => Some((7, TokenType::R7)),
+            TokenType::SP => Some((8, TokenType::SP)),
+            TokenType::FP => Some((9, TokenType::FP)),
+            _ => None,
         }
     }
+
+    /// Whether a statement, label or section can begin with this token.
+    /// The parser resynchronizes on these after an error.
+    pub fn starts_statement(&self) -> bool {
+        !matches!(
+            self,
+            TokenType::R0
+                | TokenType::R1
+                | TokenType::R2
+                | TokenType::R3
+                | TokenType::R4
+                | TokenType::R5
+                | TokenType::R6
+                | TokenType::R7
+                | TokenType::SP
+                | TokenType::FP
+                | TokenType::INT
+                | TokenType::STRING
+                | TokenType::TIMES
+                | TokenType::IDENT
+                | TokenType::Comma
+                | TokenType::LBracket
+                | TokenType::RBracket
+                | TokenType::Plus
+                | TokenType::Minus
+                | TokenType::EOF
+        )
+    }
 }
 #[derive(Debug, Clone)]
 pub struct Token {
     pub token_type: TokenType,
     pub literal: Option<String>,
     pub line_number: usize,
+    pub column: usize,
+    /// Column just past the token's last character.
+    pub end: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScanError {
+    UnknownCharacter {
+        ch: char,
+        line: usize,
+        column: usize,
+    },
+    UnterminatedString {
+        line: usize,
+        column: usize,
+    },
+    UnknownEscape {
+        ch: char,
+        line: usize,
+        column: usize,
+    },
+}
+
+impl fmt::Display for ScanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScanError::UnknownCharacter { ch, line, column } => {
+                write!(f, "unknown character {ch:?} at {line}:{column}")
+            }
+            ScanError::UnterminatedString { line, column } => {
+                write!(f, "unterminated string starting at {line}:{column}")
+            }
+            ScanError::UnknownEscape { ch, line, column } => {
+                write!(f, "unknown escape sequence \\{ch} at {line}:{column}")
+            }
+        }
+    }
 }
+
+impl std::error::Error for ScanError {}
+
 pub struct Scanner<'a> {
     data: Peekable<Chars<'a>>,
     tokens: Vec<Token>,
     line: usize,
+    column: usize,
+    start: usize,
     keywords: HashMap<String, TokenType>,
 }
 
@@ -86,37 +211,89 @@ impl<'a> Scanner<'a> {
         map.insert("r5".to_string(), TokenType::R5);
         map.insert("r6".to_string(), TokenType::R6);
         map.insert("r7".to_string(), TokenType::R7);
+        map.insert("sp".to_string(), TokenType::SP);
+        map.insert("fp".to_string(), TokenType::FP);
         map.insert("halt".to_string(), TokenType::HALT);
+        map.insert("nop".to_string(), TokenType::NOP);
         map.insert("cmp".to_string(), TokenType::CMP);
         map.insert("jmpg".to_string(), TokenType::JMPG);
         map.insert("jmpz".to_string(), TokenType::JMPZ);
         map.insert("jmple".to_string(), TokenType::JMPLE);
         map.insert("jmpge".to_string(), TokenType::JMPGE);
         map.insert("jmpl".to_string(), TokenType::JMPL);
+        map.insert("jmpnz".to_string(), TokenType::JMPNZ);
+        map.insert("jc".to_string(), TokenType::JC);
+        map.insert("jnc".to_string(), TokenType::JNC);
+        map.insert("jo".to_string(), TokenType::JO);
+        map.insert("ja".to_string(), TokenType::JA);
+        map.insert("jb".to_string(), TokenType::JB);
+        map.insert("jae".to_string(), TokenType::JAE);
+        map.insert("jbe".to_string(), TokenType::JBE);
+        map.insert("je".to_string(), TokenType::JE);
+        map.insert("jne".to_string(), TokenType::JNE);
+        map.insert("jeof".to_string(), TokenType::JEOF);
         map.insert("jmp".to_string(), TokenType::JUMP);
         map.insert("add".to_string(), TokenType::ADD);
         map.insert("print".to_string(), TokenType::Print);
+        map.insert("printc".to_string(), TokenType::PRINTC);
+        map.insert("prints".to_string(), TokenType::PRINTS);
         map.insert("sub".to_string(), TokenType::SUB);
         map.insert("call".to_string(), TokenType::Call);
         map.insert("ret".to_string(), TokenType::Ret);
         map.insert("push".to_string(), TokenType::PUSH);
         map.insert("pop".to_string(), TokenType::POP);
         map.insert("and".to_string(), TokenType::AND);
+        map.insert("not".to_string(), TokenType::NOT);
+        map.insert("pushf".to_string(), TokenType::PUSHF);
+        map.insert("popf".to_string(), TokenType::POPF);
+        map.insert("enter".to_string(), TokenType::ENTER);
+        map.insert("leave".to_string(), TokenType::LEAVE);
+        map.insert("iret".to_string(), TokenType::IRET);
+        map.insert("int".to_string(), TokenType::Interrupt);
+        map.insert("syscall".to_string(), TokenType::SYSCALL);
+        map.insert("read".to_string(), TokenType::READ);
+        map.insert("readc".to_string(), TokenType::READC);
+        map.insert("readln".to_string(), TokenType::READLN);
+        map.insert("alloc".to_string(), TokenType::ALLOC);
+        map.insert("free".to_string(), TokenType::FREE);
+        map.insert("inc".to_string(), TokenType::INC);
+        map.insert("dec".to_string(), TokenType::DEC);
+        map.insert("neg".to_string(), TokenType::NEG);
+        map.insert("test".to_string(), TokenType::TEST);
+        map.insert("shl".to_string(), TokenType::SHL);
+        map.insert("shr".to_string(), TokenType::SHR);
+        map.insert("sar".to_string(), TokenType::SAR);
+        map.insert("rol".to_string(), TokenType::ROL);
+        map.insert("ror".to_string(), TokenType::ROR);
         map.insert("or".to_string(), TokenType::OR);
         map.insert("xor".to_string(), TokenType::XOR);
         map.insert("div".to_string(), TokenType::DIV);
         map.insert("mul".to_string(), TokenType::MUL);
         map.insert("mod".to_string(), TokenType::MOD);
         map.insert(".data".to_string(), TokenType::DATA);
+        map.insert(".bss".to_string(), TokenType::BSS);
         map.insert(".code".to_string(), TokenType::CODE);
         map.insert("dd".to_string(), TokenType::DD);
         map.insert("dw".to_string(), TokenType::DW);
         map.insert("db".to_string(), TokenType::DB);
+        map.insert("times".to_string(), TokenType::TIMES);
+        map.insert("resb".to_string(), TokenType::RESB);
+        map.insert("resw".to_string(), TokenType::RESW);
+        map.insert("resd".to_string(), TokenType::RESD);
+        map.insert("loadb".to_string(), TokenType::LOADB);
+        map.insert("loadw".to_string(), TokenType::LOADW);
+        map.insert("loadd".to_string(), TokenType::LOADD);
+        map.insert("storeb".to_string(), TokenType::STOREB);
+        map.insert("storew".to_string(), TokenType::STOREW);
+        map.insert("stored".to_string(), TokenType::STORED);
+        map.insert("lea".to_string(), TokenType::LEA);
 
         Self {
             data: source.chars().peekable(),
             tokens: vec![],
             line: 1,
+            column: 0,
+            start: 0,
             keywords: map,
         }
     }
@@ -125,14 +302,60 @@ impl<'a> Scanner<'a> {
             token_type,
             literal,
             line_number: self.line,
+            column: self.start,
+            end: self.column + 1,
         };
         self.tokens.push(token);
     }
-    pub fn parse(&'a mut self) -> &'a [Token] {
-        while let Some(a) = self.data.next() {
+    fn next_char(&mut self) -> Option<char> {
+        let ch = self.data.next()?;
+        self.column += 1;
+        Some(ch)
+    }
+
+    /// Reads a string literal after its opening quote, resolving escapes.
+    fn string(&mut self) -> Result<String, ScanError> {
+        let mut string = String::new();
+        loop {
+            match self.next_char() {
+                Some('"') => return Ok(string),
+                Some('\\') => {
+                    let ch = match self.next_char() {
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        Some('r') => '\r',
+                        Some('0') => '\0',
+                        Some('\\') => '\\',
+                        Some('"') => '"',
+                        Some('\'') => '\'',
+                        Some(ch) if ch != '\n' => {
+                            return Err(ScanError::UnknownEscape {
+                                ch,
+                                line: self.line,
+                                column: self.column - 1,
+                            });
+                        }
+                        _ => break,
+                    };
+                    string.push(ch);
+                }
+                Some('\n') | None => break,
+                Some(ch) => string.push(ch),
+            }
+        }
+        Err(ScanError::UnterminatedString {
+            line: self.line,
+            column: self.start,
+        })
+    }
+
+    pub fn parse(&'a mut self) -> Result<&'a [Token], ScanError> {
+        while let Some(a) = self.next_char() {
+            self.start = self.column;
             match a {
                 '\n' => {
                     self.line += 1;
+                    self.column = 0;
                 }
 
                 ';' => {
@@ -141,7 +364,7 @@ impl<'a> Scanner<'a> {
                         if next == '\n' {
                             break;
                         }
-                        self.data.next();
+                        self.next_char();
                     }
                     continue; // Skip comment entirely
                 }
@@ -149,13 +372,30 @@ impl<'a> Scanner<'a> {
                 ',' => {
                     self.push_token(Some(a.to_string()), TokenType::Comma);
                 }
-                a if a.is_ascii_alphabetic() || a == '.' => {
+                '[' => {
+                    self.push_token(Some(a.to_string()), TokenType::LBracket);
+                }
+                ']' => {
+                    self.push_token(Some(a.to_string()), TokenType::RBracket);
+                }
+                '+' => {
+                    self.push_token(Some(a.to_string()), TokenType::Plus);
+                }
+                // a `-` directly followed by a digit is part of the number
+                '-' if !self.data.peek().is_some_and(|c| c.is_ascii_digit()) => {
+                    self.push_token(Some(a.to_string()), TokenType::Minus);
+                }
+                '"' => {
+                    let string = self.string()?;
+                    self.push_token(Some(string), TokenType::STRING);
+                }
+                a if a.is_ascii_alphabetic() || a == '.' || a == '_' => {
                     let mut str = String::new();
                     str.push(a);
                     while let Some(a) = self.data.peek()
                         && (a.is_ascii_alphanumeric() || *a == ':' || *a == '_')
                     {
-                        str.push(self.data.next().unwrap());
+                        str.push(self.next_char().unwrap());
                     }
                     str = str.trim().to_string();
                     if let Some(a) = self.keywords.get(&str.to_lowercase()) {
@@ -173,14 +413,21 @@ impl<'a> Scanner<'a> {
                     while let Some(a) = self.data.peek()
                         && a.is_ascii_digit()
                     {
-                        dig.push(self.data.next().unwrap());
+                        dig.push(self.next_char().unwrap());
                     }
                     self.push_token(Some(dig), TokenType::INT);
                 }
-                a => panic!("uknown token {a}"),
+                a => {
+                    return Err(ScanError::UnknownCharacter {
+                        ch: a,
+                        line: self.line,
+                        column: self.column,
+                    });
+                }
             }
         }
+        self.start = self.column + 1;
         self.push_token(None, TokenType::EOF);
-        &self.tokens
+        Ok(&self.tokens)
     }
 }
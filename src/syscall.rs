@@ -0,0 +1,83 @@
+use std::{
+    rc::Rc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::vm::{VM, VmError};
+
+/// A host routine behind `int N`. Arguments are in r1, r2, ... and results
+/// go back in r0, following the calling convention. Handlers can be
+/// closures carrying their own state.
+pub type Syscall = Rc<dyn Fn(&mut VM) -> Result<(), VmError>>;
+
+/// `exit r1`: stop the program with exit code r1.
+pub const EXIT: u8 = 0;
+/// `print_int r1`: print r1 as a decimal number and a newline.
+pub const PRINT_INT: u8 = 1;
+/// `print_char r1`: print the low byte of r1.
+pub const PRINT_CHAR: u8 = 2;
+/// `print_string r1`: print the null-terminated string at address r1.
+pub const PRINT_STRING: u8 = 3;
+/// `read_int -> r0`: read an input line and parse it as a number, 0 if it
+/// is not one.
+pub const READ_INT: u8 = 4;
+/// `read_line r1, r2 -> r0`: read an input line into the r2 byte buffer at r1,
+/// null-terminated and without the newline. r0 is its length, -1 at the
+/// end of input.
+pub const READ_LINE: u8 = 5;
+/// `time -> r0, r1`: seconds since the Unix epoch and the milliseconds past it.
+pub const TIME: u8 = 6;
+
+/// The handlers every [`VM`] starts with.
+pub fn defaults() -> [(u8, Syscall); 7] {
+    [
+        (EXIT, Rc::new(exit)),
+        (PRINT_INT, Rc::new(print_int)),
+        (PRINT_CHAR, Rc::new(print_char)),
+        (PRINT_STRING, Rc::new(print_string)),
+        (READ_INT, Rc::new(read_int)),
+        (READ_LINE, Rc::new(read_line)),
+        (TIME, Rc::new(time)),
+    ]
+}
+
+fn exit(vm: &mut VM) -> Result<(), VmError> {
+    vm.exit(vm.reg[1]);
+    Ok(())
+}
+
+fn print_int(vm: &mut VM) -> Result<(), VmError> {
+    vm.output(format!("{}\n", vm.reg[1]).as_bytes())
+}
+
+fn print_char(vm: &mut VM) -> Result<(), VmError> {
+    vm.output(&[vm.reg[1] as u8])
+}
+
+fn print_string(vm: &mut VM) -> Result<(), VmError> {
+    let text = vm.string_at(vm.reg[1] as u32 as usize)?;
+    vm.output(&text)
+}
+
+fn read_int(vm: &mut VM) -> Result<(), VmError> {
+    let line = vm.input_line()?.unwrap_or_default();
+    vm.reg[0] = String::from_utf8_lossy(&line).trim().parse().unwrap_or(0);
+    Ok(())
+}
+
+fn read_line(vm: &mut VM) -> Result<(), VmError> {
+    let (address, capacity) = (vm.reg[1] as u32 as usize, vm.reg[2].max(0) as usize);
+    vm.reg[0] = vm
+        .input_line_into(address, capacity)?
+        .map_or(-1, |len| len as i32);
+    Ok(())
+}
+
+fn time(vm: &mut VM) -> Result<(), VmError> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    vm.reg[0] = now.as_secs() as i32;
+    vm.reg[1] = now.subsec_millis() as i32;
+    Ok(())
+}
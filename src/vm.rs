@@ -1,464 +1,1542 @@
-use crate::parser::Data;
+use std::{
+    collections::HashMap,
+    fmt,
+    io::{self, BufRead, BufReader, Read, Write},
+    rc::Rc,
+    time::Instant,
+};
 
-const CARRY_FLAG: u8 = 0b0000_0010;
+use crate::{
+    heap::{FreeError, Heap, HeapStats},
+    object::Object,
+    syscall::{self, Syscall},
+};
+
+// Every ALU instruction (add, sub, mul, div, mod, and, or, xor, not, the
+// shifts, inc, dec, neg, test and cmp) rewrites all six flags from its
+// result; mov, load, store, push and pop leave them alone.
+//
+//   Z  result is zero
+//   C  unsigned carry out of add, borrow out of sub/cmp/neg, product or
+//      quotient that does not fit, last bit out of a shift; clear for logic
+//   O  signed overflow, same cases as C for mul/div; clear for logic/shifts
+//   S  result is negative
+//   G  result > 0, or lhs > rhs (signed) for cmp
+//   L  result < 0, or lhs < rhs (signed) for cmp
+//
+// E is not an ALU flag: input instructions set it when they hit the end of
+// input and clear it after a successful read.
 const ZERO_FLAG: u8 = 0b0000_0001;
+const CARRY_FLAG: u8 = 0b0000_0010;
+const OVERFLOW_FLAG: u8 = 0b0000_0100;
+const SIGN_FLAG: u8 = 0b0000_1000;
 const GRETER_FLAG: u8 = 0b0001_0000;
 const LESSER_FLAG: u8 = 0b0010_0000;
+const EOF_FLAG: u8 = 0b0100_0000;
 type Flags = u8;
 
 const CODE_START: usize = 0x0000;
-const DATA_START: usize = 0x2000;
-const BSS_START: usize = 0x3000;
-const HEAP_START: usize = 0x4000;
-const STACK_START: usize = MEMORY_SIZE - 1;
-const STACK_END: usize = HEAP_START;
-const MEMORY_SIZE: usize = 64 * 1024;
+// the last bytes of the bss segment hold the trap vector table
+const VECTORS: usize = 8;
+// bytes below the stack limit a trap frame may use
+const TRAP_RED_ZONE: usize = 8;
+
+/// Memory size and layout. Code starts at 0 and runs up to `data_start`,
+/// the bss segment ends with the trap vector table, the stack takes the
+/// top `stack_size` bytes and the heap lies between the bss and the stack.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VmConfig {
+    pub memory_size: usize,
+    pub data_start: usize,
+    pub bss_start: usize,
+    pub heap_start: usize,
+    pub stack_size: usize,
+}
+
+impl Default for VmConfig {
+    fn default() -> Self {
+        Self {
+            memory_size: 64 * 1024,
+            data_start: 0x2000,
+            bss_start: 0x3000,
+            heap_start: 0x4000,
+            stack_size: 16 * 1024,
+        }
+    }
+}
+
+impl VmConfig {
+    /// Lowest address the stack may grow down to, 0 for a stack larger
+    /// than memory, which `validate` rejects.
+    pub fn stack_end(&self) -> usize {
+        self.memory_size.saturating_sub(self.stack_size)
+    }
+
+    /// End of the heap, short of the stack by the trap red zone.
+    fn heap_end(&self) -> usize {
+        self.stack_end().saturating_sub(TRAP_RED_ZONE)
+    }
+
+    /// Initial `sp` and `fp`.
+    fn stack_start(&self) -> usize {
+        self.memory_size - 1
+    }
+
+    fn vector_table(&self) -> usize {
+        self.heap_start - VECTORS * 4
+    }
+
+    /// Checks that the segments are in order and fit in memory.
+    pub fn validate(&self) -> Result<(), VmError> {
+        let reason = if self.data_start == CODE_START {
+            "the code segment is empty"
+        } else if self.bss_start < self.data_start {
+            "the bss segment starts before the data segment"
+        } else if self
+            .bss_start
+            .checked_add(VECTORS * 4)
+            .is_none_or(|table_end| self.heap_start < table_end)
+        {
+            "the bss segment has no room for the trap vector table"
+        } else if self
+            .heap_start
+            .checked_add(TRAP_RED_ZONE)
+            .zip(self.memory_size.checked_sub(self.stack_size))
+            .is_none_or(|(heap_end, stack_end)| heap_end > stack_end)
+        {
+            "the stack overlaps the bss segment"
+        } else if self.stack_size < TRAP_RED_ZONE {
+            "the stack is too small"
+        } else if self.memory_size > i32::MAX as usize {
+            "memory is larger than a register can address"
+        } else {
+            return Ok(());
+        };
+        Err(VmError::InvalidConfig {
+            reason: reason.to_string(),
+        })
+    }
+}
+
+/// Access rights of a memory region, a mask of [`READ`], [`WRITE`] and
+/// [`EXECUTE`].
+pub type Permissions = u8;
+pub const READ: Permissions = 0b001;
+pub const WRITE: Permissions = 0b010;
+pub const EXECUTE: Permissions = 0b100;
+
+/// A kind of memory access, checked against the region's permissions.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Access {
+    Read,
+    Write,
+    Execute,
+}
+
+impl Access {
+    fn permission(self) -> Permissions {
+        match self {
+            Access::Read => READ,
+            Access::Write => WRITE,
+            Access::Execute => EXECUTE,
+        }
+    }
+}
+
+impl fmt::Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Access::Read => write!(f, "read"),
+            Access::Write => write!(f, "write"),
+            Access::Execute => write!(f, "execute"),
+        }
+    }
+}
+
+/// `start..end` of memory with the same permissions.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Region {
+    pub start: usize,
+    pub end: usize,
+    pub permissions: Permissions,
+}
+
+/// Code is execute-only; data, bss, heap and stack are read-write.
+fn default_regions(config: &VmConfig) -> Vec<Region> {
+    [
+        (CODE_START, config.data_start, EXECUTE),
+        (config.data_start, config.bss_start, READ | WRITE),
+        (config.bss_start, config.heap_start, READ | WRITE),
+        (config.heap_start, config.stack_end(), READ | WRITE),
+        (config.stack_end(), config.memory_size, READ | WRITE),
+    ]
+    .into_iter()
+    .map(|(start, end, permissions)| Region {
+        start,
+        end,
+        permissions,
+    })
+    .collect()
+}
+
+// r0..r7 are general purpose, the stack and frame pointers follow them so
+// every instruction that takes a register also takes `sp` and `fp`.
+pub const SP: usize = 8;
+pub const FP: usize = 9;
+const REGISTERS: usize = 10;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum VmError {
+    StackOverflow { pc: u32 },
+    StackUnderflow { pc: u32 },
+    InvalidOpcode { pc: u32, opcode: u8 },
+    InvalidRegister { pc: u32, register: u8 },
+    SegFault { pc: u32, address: usize },
+    ProtectionFault { pc: u32, address: usize, access: Access },
+    DivideByZero { pc: u32 },
+    PcOutOfBounds { pc: u32 },
+    InvalidConfig { reason: String },
+    OutOfMemory { pc: u32, size: i32 },
+    DoubleFree { pc: u32, address: usize },
+    InvalidFree { pc: u32, address: usize },
+    UnknownSyscall { pc: u32, number: i32 },
+    Io { pc: u32, message: String },
+}
+
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VmError::StackOverflow { pc } => write!(f, "stack overflow at pc {pc:#06x}"),
+            VmError::StackUnderflow { pc } => write!(f, "stack underflow at pc {pc:#06x}"),
+            VmError::InvalidOpcode { pc, opcode } => {
+                write!(f, "invalid opcode {opcode:#04x} at pc {pc:#06x}")
+            }
+            VmError::InvalidRegister { pc, register } => {
+                write!(f, "invalid register {register} at pc {pc:#06x}")
+            }
+            VmError::SegFault { pc, address } => {
+                write!(
+                    f,
+                    "segmentation fault accessing {address:#06x} at pc {pc:#06x}"
+                )
+            }
+            VmError::ProtectionFault { pc, address, access } => {
+                write!(
+                    f,
+                    "protection fault: {access} access to {address:#06x} at pc {pc:#06x}"
+                )
+            }
+            VmError::DivideByZero { pc } => write!(f, "divide by zero at pc {pc:#06x}"),
+            VmError::PcOutOfBounds { pc } => write!(f, "pc {pc:#06x} out of bounds"),
+            VmError::InvalidConfig { reason } => write!(f, "invalid memory layout: {reason}"),
+            VmError::OutOfMemory { pc, size } => {
+                write!(f, "out of heap memory allocating {size} bytes at pc {pc:#06x}")
+            }
+            VmError::DoubleFree { pc, address } => {
+                write!(f, "double free of {address:#06x} at pc {pc:#06x}")
+            }
+            VmError::InvalidFree { pc, address } => {
+                write!(f, "free of {address:#06x}, which is not a heap block, at pc {pc:#06x}")
+            }
+            VmError::UnknownSyscall { pc, number } => {
+                write!(f, "no handler for interrupt {number} at pc {pc:#06x}")
+            }
+            VmError::Io { pc, message } => write!(f, "i/o error at pc {pc:#06x}: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for VmError {}
+
+/// Why an object could not be loaded, see [`VM::load`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum LoadError {
+    ProgramTooLarge { size: usize, limit: usize },
+    InvalidEntry { entry: u32 },
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::ProgramTooLarge { size, limit } => {
+                write!(
+                    f,
+                    "segment of {size} bytes exceeds its limit of {limit} bytes"
+                )
+            }
+            LoadError::InvalidEntry { entry } => {
+                write!(f, "entry point {entry:#06x} is outside the code section")
+            }
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+/// Trap vectors, each a slot in the vector table holding the code address of
+/// a handler, or 0 to hand the fault to the host.
+pub const TRAP_DIVIDE_BY_ZERO: u8 = 0;
+pub const TRAP_INVALID_INSTRUCTION: u8 = 1;
+pub const TRAP_SEGMENT_VIOLATION: u8 = 2;
+pub const TRAP_STACK_OVERFLOW: u8 = 3;
+pub const TRAP_STACK_UNDERFLOW: u8 = 4;
+pub const TRAP_HEAP: u8 = 5;
+
+impl VmError {
+    /// The trap vector a program can catch this fault with, if any.
+    pub fn vector(&self) -> Option<u8> {
+        match self {
+            VmError::DivideByZero { .. } => Some(TRAP_DIVIDE_BY_ZERO),
+            VmError::InvalidOpcode { .. } | VmError::InvalidRegister { .. } => {
+                Some(TRAP_INVALID_INSTRUCTION)
+            }
+            VmError::SegFault { .. } | VmError::ProtectionFault { .. } => {
+                Some(TRAP_SEGMENT_VIOLATION)
+            }
+            VmError::StackOverflow { .. } => Some(TRAP_STACK_OVERFLOW),
+            VmError::StackUnderflow { .. } => Some(TRAP_STACK_UNDERFLOW),
+            VmError::OutOfMemory { .. }
+            | VmError::DoubleFree { .. }
+            | VmError::InvalidFree { .. } => Some(TRAP_HEAP),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Status {
+    Running,
+    Halted,
+    /// The program called the exit syscall with this code.
+    Exited(i32),
+}
+
+/// How a run of [`VM::execute`] ended.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Outcome {
+    /// The program halted (code 0) or called the exit syscall.
+    Finished(i32),
+    /// `limit` ran out before the instruction at `pc`. Raising the limit
+    /// and calling `execute` again resumes there.
+    BudgetExhausted { pc: u32, limit: Limit },
+}
+
+/// The bound that stopped a run, see [`VM::set_budget`] and
+/// [`VM::set_deadline`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Limit {
+    Instructions,
+    Deadline,
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Limit::Instructions => write!(f, "instruction budget exhausted"),
+            Limit::Deadline => write!(f, "deadline passed"),
+        }
+    }
+}
+
+// reading the clock on every instruction would dominate a tight loop
+const DEADLINE_CHECK_INTERVAL: u64 = 1024;
+
+/// A `call` that has not returned yet.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Frame {
+    pub caller: u32,
+    pub callee: u32,
+    /// Stack address of the return address; the frame is over once `sp`
+    /// moves above it, whether through `ret` or not.
+    pub slot: u32,
+}
 
 pub struct VM {
     flag: Flags,
     pc: u32,
-    sp: usize,
-    pub reg: [i32; 8],
-    pub memory: [u8; MEMORY_SIZE],
+    // address of the instruction being executed, used to report faults
+    current: u32,
+    call_stack: Vec<Frame>,
+    regions: Vec<Region>,
+    syscalls: HashMap<u8, Syscall>,
+    exit_code: Option<i32>,
+    heap: Heap,
+    // instructions `execute` may still run, unbounded when `None`
+    budget: Option<u64>,
+    deadline: Option<Instant>,
+    executed: u64,
+    input: Box<dyn BufRead>,
+    output: Box<dyn Write>,
+    // messages about the machine rather than from the program, e.g. "Halt"
+    diagnostics: Box<dyn Write>,
+    config: VmConfig,
+    pub reg: [i32; REGISTERS],
+    pub memory: Vec<u8>,
 }
 impl Default for VM {
     fn default() -> Self {
-        Self {
+        Self::with_config(VmConfig::default()).expect("the default layout is valid")
+    }
+}
+
+impl VM {
+    /// A machine with the memory size and layout of `config`.
+    pub fn with_config(config: VmConfig) -> Result<Self, VmError> {
+        config.validate()?;
+        Ok(Self {
             flag: 0,
             pc: CODE_START as u32,
-            sp: STACK_START,
-            reg: [0; 8],
-            memory: [0; MEMORY_SIZE],
+            current: CODE_START as u32,
+            call_stack: vec![],
+            regions: default_regions(&config),
+            syscalls: HashMap::from(syscall::defaults()),
+            exit_code: None,
+            heap: Heap::new(config.heap_start, config.heap_end()),
+            budget: None,
+            deadline: None,
+            executed: 0,
+            input: Box::new(BufReader::new(io::stdin())),
+            output: Box::new(io::stdout()),
+            diagnostics: Box::new(io::stderr()),
+            config,
+            reg: stack_registers(&config),
+            memory: vec![0; config.memory_size],
+        })
+    }
+
+    pub fn config(&self) -> &VmConfig {
+        &self.config
+    }
+
+    pub fn pc(&self) -> u32 {
+        self.pc
+    }
+
+    /// Address of the instruction being executed, for reporting faults.
+    pub fn current(&self) -> u32 {
+        self.current
+    }
+
+    /// Lets `execute` run at most `instructions` more instructions, or any
+    /// number with `None`.
+    pub fn set_budget(&mut self, instructions: Option<u64>) {
+        self.budget = instructions;
+    }
+
+    /// Instructions left in the budget.
+    pub fn budget(&self) -> Option<u64> {
+        self.budget
+    }
+
+    /// Stops `execute` once `deadline` has passed. The clock is read every
+    /// thousand or so instructions, so a run may go slightly over.
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.deadline = deadline;
+    }
+
+    /// Instructions executed by `execute` so far, over all runs.
+    pub fn executed(&self) -> u64 {
+        self.executed
+    }
+
+    /// Installs `handler` as `int number`, replacing any handler there.
+    pub fn register_syscall(
+        &mut self,
+        number: u8,
+        handler: impl Fn(&mut VM) -> Result<(), VmError> + 'static,
+    ) {
+        self.syscalls.insert(number, Rc::new(handler));
+    }
+
+    /// Stops the program once the current instruction finishes.
+    pub fn exit(&mut self, code: i32) {
+        self.exit_code = Some(code);
+    }
+
+    /// Reads program input from `input` instead of stdin.
+    pub fn set_input(&mut self, input: impl Read + 'static) {
+        self.input = Box::new(BufReader::new(input));
+    }
+
+    /// Sends program output to `output` instead of stdout.
+    pub fn set_output(&mut self, output: impl Write + 'static) {
+        self.output = Box::new(output);
+    }
+
+    /// Sends messages about the machine, such as "Halt", to `diagnostics`
+    /// instead of stderr.
+    pub fn set_diagnostics(&mut self, diagnostics: impl Write + 'static) {
+        self.diagnostics = Box::new(diagnostics);
+    }
+
+    /// Writes `bytes` to the program output.
+    pub fn output(&mut self, bytes: &[u8]) -> Result<(), VmError> {
+        self.output
+            .write_all(bytes)
+            .map_err(|err| self.io_error(err))
+    }
+
+    /// The null-terminated string at `address`, without the terminator.
+    pub fn string_at(&self, mut address: usize) -> Result<Vec<u8>, VmError> {
+        let mut text = vec![];
+        loop {
+            let byte = self.read(address, 1)? as u8;
+            if byte == 0 {
+                return Ok(text);
+            }
+            text.push(byte);
+            address += 1;
         }
     }
-}
 
-impl VM {
-    pub fn push(&mut self, value: i32) {
-        assert!(self.sp >= STACK_END, "stack overflow");
-        self.sp -= 4;
-        self.memory[self.sp..self.sp + 4].copy_from_slice(&value.to_be_bytes());
+    /// Flushes the program output and reports `message` on the diagnostic
+    /// channel, once the program stops.
+    fn stop(&mut self, message: &str) -> Result<(), VmError> {
+        let result = self
+            .output
+            .flush()
+            .and_then(|_| writeln!(self.diagnostics, "{message}"));
+        result.map_err(|err| self.io_error(err))
+    }
+
+    /// Next input line without its line ending, `None` at the end of input.
+    /// Sets E at the end of input and clears it otherwise.
+    pub fn input_line(&mut self) -> Result<Option<Vec<u8>>, VmError> {
+        let mut line = vec![];
+        let read = self
+            .input
+            .read_until(b'\n', &mut line)
+            .map_err(|err| self.io_error(err))?;
+        if !self.input_status(read > 0) {
+            return Ok(None);
+        }
+        while line
+            .last()
+            .is_some_and(|byte| matches!(byte, b'\n' | b'\r'))
+        {
+            line.pop();
+        }
+        Ok(Some(line))
+    }
+
+    /// Next input byte, `None` at the end of input. Sets E like
+    /// [`VM::input_line`].
+    pub fn input_byte(&mut self) -> Result<Option<u8>, VmError> {
+        let mut byte = [0];
+        let read = self
+            .input
+            .read(&mut byte)
+            .map_err(|err| self.io_error(err))?;
+        Ok(self.input_status(read > 0).then_some(byte[0]))
+    }
+
+    /// Reads a line into the `capacity` byte buffer at `address`, cut to fit
+    /// and null-terminated. Returns its length, `None` at the end of input.
+    pub fn input_line_into(
+        &mut self,
+        address: usize,
+        capacity: usize,
+    ) -> Result<Option<usize>, VmError> {
+        let Some(line) = self.input_line()? else {
+            return Ok(None);
+        };
+        if capacity == 0 {
+            return Ok(Some(0));
+        }
+        // keep room for the terminator
+        let bytes = &line[..line.len().min(capacity - 1)];
+        for (i, byte) in bytes.iter().chain(&[0]).enumerate() {
+            self.write(address + i, 1, *byte as i32)?;
+        }
+        Ok(Some(bytes.len()))
+    }
+
+    /// Updates E after a read, returning whether anything was read.
+    fn input_status(&mut self, read: bool) -> bool {
+        if read {
+            self.flag &= !EOF_FLAG;
+        } else {
+            self.flag |= EOF_FLAG;
+        }
+        read
+    }
+
+    fn io_error(&self, err: io::Error) -> VmError {
+        VmError::Io {
+            pc: self.current,
+            message: err.to_string(),
+        }
+    }
+
+    /// Allocation counters of `alloc` and `free` so far.
+    pub fn heap_stats(&self) -> HeapStats {
+        self.heap.stats()
+    }
+
+    pub fn regions(&self) -> &[Region] {
+        &self.regions
+    }
+
+    /// Gives `start..end` new permissions. Later calls take precedence
+    /// where ranges overlap.
+    pub fn protect(&mut self, start: usize, end: usize, permissions: Permissions) {
+        self.regions.push(Region {
+            start,
+            end,
+            permissions,
+        });
+    }
+
+    /// Permissions of the byte at `address`, none outside every region.
+    fn permissions(&self, address: usize) -> Permissions {
+        self.regions
+            .iter()
+            .rev()
+            .find(|region| (region.start..region.end).contains(&address))
+            .map_or(0, |region| region.permissions)
+    }
+
+    /// Checks a `width` byte access at `address`: outside memory is a
+    /// segmentation fault, a region without the permission a protection
+    /// fault.
+    fn check(&self, address: usize, width: usize, access: Access) -> Result<(), VmError> {
+        let pc = self.current;
+        if address + width > self.config.memory_size {
+            return Err(VmError::SegFault { pc, address });
+        }
+        let permission = access.permission();
+        match (address..address + width).find(|at| self.permissions(*at) & permission == 0) {
+            Some(address) => Err(VmError::ProtectionFault {
+                pc,
+                address,
+                access,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    pub fn sp(&self) -> usize {
+        self.reg[SP] as u32 as usize
+    }
+
+    pub fn call_stack(&self) -> &[Frame] {
+        &self.call_stack
+    }
+
+    pub fn flags(&self) -> Flags {
+        self.flag
+    }
+
+    /// The flag register decoded to its named bits, e.g. `Z=1 C=0 S=0 O=0 G=0 L=0 E=0`.
+    pub fn describe_flags(&self) -> String {
+        [
+            ("Z", ZERO_FLAG),
+            ("C", CARRY_FLAG),
+            ("S", SIGN_FLAG),
+            ("O", OVERFLOW_FLAG),
+            ("G", GRETER_FLAG),
+            ("L", LESSER_FLAG),
+            ("E", EOF_FLAG),
+        ]
+        .iter()
+        .map(|(name, bit)| format!("{name}={}", (self.flag & bit != 0) as u8))
+        .collect::<Vec<_>>()
+        .join(" ")
     }
 
-    pub fn pop(&mut self) -> i32 {
-        assert!(self.sp < STACK_START, "stack underflow");
+    pub fn push(&mut self, value: i32) -> Result<(), VmError> {
+        let sp = self.sp();
+        if sp < self.config.stack_end() + 4 {
+            return Err(VmError::StackOverflow { pc: self.current });
+        }
+        if sp > self.config.stack_start() {
+            return Err(VmError::StackUnderflow { pc: self.current });
+        }
+        let sp = sp - 4;
+        self.check(sp, 4, Access::Write)?;
+        self.memory[sp..sp + 4].copy_from_slice(&value.to_le_bytes());
+        self.reg[SP] = sp as i32;
+        Ok(())
+    }
 
-        let val: [u8; 4] = self.memory[self.sp..self.sp + 4].try_into().unwrap();
-        self.sp += 4;
-        i32::from_be_bytes(val)
+    pub fn pop(&mut self) -> Result<i32, VmError> {
+        let sp = self.sp();
+        let value = self.stack_slot(sp)?;
+        self.reg[SP] = (sp + 4) as i32;
+        Ok(value)
     }
 
-    pub fn extract_u32(&mut self) -> u32 {
-        assert!(self.pc + 3 < DATA_START as u32, "PC out of bounds");
-        let lsb0 = self.memory[self.pc as usize];
-        let lsb1 = self.memory[(self.pc + 1) as usize];
-        let lsb2 = self.memory[(self.pc + 2) as usize];
-        let lsb3 = self.memory[(self.pc + 3) as usize];
-        self.pc += 4;
-        u32::from_le_bytes([lsb0, lsb1, lsb2, lsb3])
+    /// The value `pop` would return with `sp` at `sp`, without popping it.
+    fn stack_slot(&self, sp: usize) -> Result<i32, VmError> {
+        if sp + 4 > self.config.stack_start() {
+            return Err(VmError::StackUnderflow { pc: self.current });
+        }
+        if sp < self.config.stack_end() {
+            return Err(VmError::StackOverflow { pc: self.current });
+        }
+
+        self.check(sp, 4, Access::Read)?;
+        let val: [u8; 4] = self.memory[sp..sp + 4].try_into().unwrap();
+        Ok(i32::from_le_bytes(val))
     }
 
-    pub fn execute(&mut self) {
+    fn register(&self, index: u8) -> Result<usize, VmError> {
+        if (index as usize) < self.reg.len() {
+            Ok(index as usize)
+        } else {
+            Err(VmError::InvalidRegister {
+                pc: self.current,
+                register: index,
+            })
+        }
+    }
+
+    /// Address of a symbolic operand: an offset into the data segment, or
+    /// into the bss segment when bit 6 of `inst2` is set.
+    fn symbol_address(&self, inst2: u8, inst3: u8, inst4: u8) -> Result<usize, VmError> {
+        let (start, end) = if inst2 & 0x40 != 0 {
+            (self.config.bss_start, self.config.heap_start)
+        } else {
+            (self.config.data_start, self.config.bss_start)
+        };
+        let offset = start + u16::from_be_bytes([inst3, inst4]) as usize;
+        if (start..end).contains(&offset) {
+            Ok(offset)
+        } else {
+            Err(VmError::SegFault {
+                pc: self.current,
+                address: offset,
+            })
+        }
+    }
+
+    /// Address of a `[rN + imm]` operand: the base register sits in the low
+    /// nibble of `inst2` and the signed offset in `inst3`/`inst4`. Addresses
+    /// outside memory are clamped so the access check rejects them.
+    fn indirect_address(&self, inst2: u8, inst3: u8, inst4: u8) -> Result<usize, VmError> {
+        let base = self.register(inst2 & 0x0F)?;
+        let offset = i16::from_be_bytes([inst3, inst4]) as i64;
+        let address = self.reg[base] as i64 + offset;
+        Ok(address.clamp(0, self.config.memory_size as i64) as usize)
+    }
+
+    /// Reads `width` bytes (1, 2 or 4) little-endian and sign-extends them.
+    pub fn read(&self, address: usize, width: usize) -> Result<i32, VmError> {
+        self.check(address, width, Access::Read)?;
+        let bytes = &self.memory[address..address + width];
+        Ok(match width {
+            1 => bytes[0] as i8 as i32,
+            2 => i16::from_le_bytes([bytes[0], bytes[1]]) as i32,
+            _ => i32::from_le_bytes(bytes.try_into().unwrap()),
+        })
+    }
+
+    /// Writes the low `width` bytes of `value` little-endian.
+    pub fn write(&mut self, address: usize, width: usize, value: i32) -> Result<(), VmError> {
+        self.check(address, width, Access::Write)?;
+        self.memory[address..address + width].copy_from_slice(&value.to_le_bytes()[..width]);
+        Ok(())
+    }
+
+    /// Immediate operand of an instruction: an `i16` in `inst3`/`inst4`, or
+    /// the whole next word when bit 7 of `inst2` is set.
+    fn immediate(&mut self, inst2: u8, inst3: u8, inst4: u8) -> Result<i32, VmError> {
+        if inst2 & 0x80 != 0 {
+            Ok(self.extract_u32()? as i32)
+        } else {
+            Ok(i16::from_be_bytes([inst3, inst4]) as i32)
+        }
+    }
+
+    /// Destination of a jump or call: a signed 24-bit byte offset from the
+    /// start of the branch instruction.
+    fn branch_target(&self, inst2: u8, inst3: u8, inst4: u8) -> u32 {
+        let offset = i32::from_be_bytes([inst2, inst3, inst4, 0x00]) >> 8;
+        self.current.wrapping_add_signed(offset)
+    }
+
+    /// Shifts (kind 0 shl, 1 shr, 2 sar) or rotates (3 rol, 4 ror) `value` by
+    /// `count` modulo 32. C receives the last bit shifted out, or for
+    /// rotates the bit that wrapped around; O is cleared.
+    fn shift(&mut self, kind: u8, value: i32, count: i32) -> i32 {
+        let count = (count & 31) as u32;
+        let bits = value as u32;
+        let (res, carry) = match kind {
+            _ if count == 0 => (bits, false),
+            0 => (bits << count, bits >> (32 - count) & 1 != 0),
+            1 => (bits >> count, bits >> (count - 1) & 1 != 0),
+            2 => ((value >> count) as u32, value >> (count - 1) & 1 != 0),
+            3 => {
+                let res = bits.rotate_left(count);
+                (res, res & 1 != 0)
+            }
+            _ => {
+                let res = bits.rotate_right(count);
+                (res, res >> 31 != 0)
+            }
+        };
+        self.set_flags(res as i32, carry, false);
+        res as i32
+    }
+
+    /// Sets Z, S, C and O for `result`, and G/L by comparing it with zero.
+    /// Only these six bits are written; E is left alone.
+    fn set_flags(&mut self, result: i32, carry: bool, overflow: bool) {
+        self.flag &=
+            !(ZERO_FLAG | SIGN_FLAG | CARRY_FLAG | OVERFLOW_FLAG | GRETER_FLAG | LESSER_FLAG);
+        let bits = [
+            (result == 0, ZERO_FLAG),
+            (result < 0, SIGN_FLAG),
+            (carry, CARRY_FLAG),
+            (overflow, OVERFLOW_FLAG),
+            (result > 0, GRETER_FLAG),
+            (result < 0, LESSER_FLAG),
+        ];
+        for (set, bit) in bits {
+            if set {
+                self.flag |= bit;
+            }
+        }
+    }
+
+    /// `lhs + rhs`, C on unsigned carry and O on signed overflow.
+    fn add(&mut self, lhs: i32, rhs: i32) -> i32 {
+        let (res, overflow) = lhs.overflowing_add(rhs);
+        let carry = (lhs as u32).overflowing_add(rhs as u32).1;
+        self.set_flags(res, carry, overflow);
+        res
+    }
+
+    /// `lhs - rhs`, C on unsigned borrow and O on signed overflow.
+    fn sub(&mut self, lhs: i32, rhs: i32) -> i32 {
+        let (res, overflow) = lhs.overflowing_sub(rhs);
+        self.set_flags(res, (lhs as u32) < (rhs as u32), overflow);
+        res
+    }
+
+    /// `lhs * rhs`, C and O when the product does not fit in 32 bits.
+    fn mul(&mut self, lhs: i32, rhs: i32) -> i32 {
+        let (res, overflow) = lhs.overflowing_mul(rhs);
+        self.set_flags(res, overflow, overflow);
+        res
+    }
+
+    /// `lhs / rhs` or `lhs % rhs`, C and O for `i32::MIN / -1`.
+    fn div(&mut self, lhs: i32, rhs: i32, rem: bool) -> Result<i32, VmError> {
+        if rhs == 0 {
+            return Err(VmError::DivideByZero { pc: self.current });
+        }
+        let (res, overflow) = if rem {
+            lhs.overflowing_rem(rhs)
+        } else {
+            lhs.overflowing_div(rhs)
+        };
+        self.set_flags(res, overflow, overflow);
+        Ok(res)
+    }
+
+    /// Result of a bitwise operation, C and O cleared.
+    fn logic(&mut self, result: i32) -> i32 {
+        self.set_flags(result, false, false);
+        result
+    }
+
+    /// Flags as for `lhs - rhs`, except G and L which compare `lhs` with
+    /// `rhs` as signed numbers, so equal operands set neither.
+    fn compare(&mut self, lhs: i32, rhs: i32) {
+        self.sub(lhs, rhs);
+        self.flag &= !(GRETER_FLAG | LESSER_FLAG);
+        if lhs > rhs {
+            self.flag |= GRETER_FLAG;
+        } else if lhs < rhs {
+            self.flag |= LESSER_FLAG;
+        }
+    }
+
+    /// Fetches the next instruction word, which must be executable.
+    /// The pc moves past the word even when the fetch faults, so a trap
+    /// handler returns after it rather than fetching it again.
+    pub fn extract_u32(&mut self) -> Result<u32, VmError> {
+        let pc = self.pc as usize;
+        self.pc = self.pc.wrapping_add(4);
+        if pc + 4 > self.config.memory_size {
+            return Err(VmError::PcOutOfBounds { pc: pc as u32 });
+        }
+        self.check(pc, 4, Access::Execute)?;
+        Ok(u32::from_le_bytes(self.memory[pc..pc + 4].try_into().unwrap()))
+    }
+
+    /// Runs until the program stops, with its exit code: 0 after a `halt`,
+    /// or the code passed to the exit syscall. A run that uses up the
+    /// instruction budget or passes the deadline stops early instead.
+    pub fn execute(&mut self) -> Result<Outcome, VmError> {
+        loop {
+            if let Some(limit) = self.exhausted() {
+                self.output.flush().map_err(|err| self.io_error(err))?;
+                return Ok(Outcome::BudgetExhausted { pc: self.pc, limit });
+            }
+            let status = self.step();
+            self.executed += 1;
+            if let Some(budget) = &mut self.budget {
+                *budget -= 1;
+            }
+            match status {
+                Ok(Status::Running) => {}
+                Ok(Status::Halted) => return Ok(Outcome::Finished(0)),
+                Ok(Status::Exited(code)) => return Ok(Outcome::Finished(code)),
+                Err(err) => {
+                    // keep whatever the program printed before the fault
+                    let _ = self.output.flush();
+                    return Err(err);
+                }
+            }
+        }
+    }
+
+    /// The limit that keeps `execute` from running another instruction.
+    fn exhausted(&self) -> Option<Limit> {
+        if self.budget == Some(0) {
+            return Some(Limit::Instructions);
+        }
+        if self.executed.is_multiple_of(DEADLINE_CHECK_INTERVAL)
+            && self.deadline.is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            return Some(Limit::Deadline);
+        }
+        None
+    }
+
+    /// Fetches, decodes and executes a single instruction. A fault the
+    /// program has a trap handler for transfers control to the handler;
+    /// any other fault is returned.
+    pub fn step(&mut self) -> Result<Status, VmError> {
+        let status = self.dispatch().or_else(|err| self.trap(err));
+        let sp = self.sp() as u32;
+        while self.call_stack.last().is_some_and(|frame| frame.slot < sp) {
+            self.call_stack.pop();
+        }
+        status
+    }
+
+    /// Enters the handler for `err`: pushes the flags and the address of
+    /// the instruction after the faulting one, then jumps. For a fault
+    /// fetching an instruction that is the word after the one fetched. The
+    /// trap frame may use a few bytes below the stack limit, so a stack
+    /// overflow handler still runs. Without a handler, or when the frame
+    /// does not fit, the fault goes to the host.
+    fn trap(&mut self, err: VmError) -> Result<Status, VmError> {
+        let Some(vector) = err.vector() else {
+            return Err(err);
+        };
+        let slot = self.config.vector_table() + vector as usize * 4;
+        let handler = u32::from_le_bytes(self.memory[slot..slot + 4].try_into().unwrap());
+        let sp = self.sp();
+        let fits =
+            sp + TRAP_RED_ZONE >= self.config.stack_end() + 8 && sp <= self.config.memory_size;
+        if handler == 0 || !fits {
+            return Err(err);
+        }
+        let frame = [self.flag as i32, self.pc as i32];
+        for (i, value) in frame.iter().enumerate() {
+            let at = sp - 4 * (i + 1);
+            self.memory[at..at + 4].copy_from_slice(&value.to_le_bytes());
+        }
+        self.reg[SP] = (sp - 8) as i32;
+        self.pc = handler;
+        Ok(Status::Running)
+    }
+
+    fn dispatch(&mut self) -> Result<Status, VmError> {
         // [opcode (8 bits ) | rest ----]
         // little endian bytes for memory structure MSB at last and LSB first
-        'lp: loop {
-            let ins = self.extract_u32();
-            let [inst1, inst2, inst3, inst4] = ins.to_be_bytes();
-            // println!("{:?}",self.reg);
-            //println!("i1 {inst1} i2 {inst2} i3 {inst3} i4 {inst4}");
-            let op_code = inst1;
-            match op_code {
-                0x00 => {}
-                0xFF => {
-                    println!("Halt");
-                    break 'lp;
-                }
-                // Mov rn,i16
-                0x01 => {
-                    let reg = inst2 as usize;
-                    let value = i16::from_be_bytes([inst3, inst4]) as i32;
-                    self.reg[reg] = value;
-                }
-                // Add rn + rm
-                0x02 => {
-                    self.flag &= !CARRY_FLAG;
-                    self.flag &= !ZERO_FLAG;
-                    let n = inst2 as usize;
-                    let m = inst3 as usize;
-                    let overflow = self.reg[n].overflowing_add(self.reg[m]);
-                    if overflow.1 {
-                        self.flag |= CARRY_FLAG;
-                    }
-                    if overflow.0 == 0 {
-                        self.flag |= ZERO_FLAG;
-                    }
-                    self.reg[n] = overflow.0;
-                }
-                // Add rn + imm
-                0x03 => {
-                    self.flag &= !CARRY_FLAG;
-                    self.flag &= !ZERO_FLAG;
-                    let n = inst2 as usize;
-                    let m = i16::from_be_bytes([inst3, inst4]) as i32;
-                    let overflow = self.reg[n].overflowing_add(m);
-                    if overflow.1 {
-                        self.flag |= CARRY_FLAG;
-                    }
-                    if overflow.0 == 0 {
-                        self.flag |= ZERO_FLAG;
-                    }
-                    self.reg[n] = overflow.0;
-                }
-                // sub rn + rm
-                0x04 => {
-                    self.flag &= !CARRY_FLAG;
-                    self.flag &= !ZERO_FLAG;
-                    let n = inst2 as usize;
-                    let m = inst3 as usize;
-                    let overflow = self.reg[n].overflowing_sub(self.reg[m]);
-                    if overflow.1 {
-                        self.flag |= CARRY_FLAG;
-                    }
-                    if overflow.0 == 0 {
-                        self.flag |= ZERO_FLAG;
-                    }
-                    self.reg[n] = overflow.0;
-                }
-                // sub rn + imm
-                0x05 => {
-                    self.flag &= !CARRY_FLAG;
-                    self.flag &= !ZERO_FLAG;
-                    let n = inst2 as usize;
-                    let m = i16::from_be_bytes([inst3, inst4]) as i32;
-                    let overflow = self.reg[n].overflowing_sub(m);
-                    if overflow.1 {
-                        self.flag |= CARRY_FLAG;
-                    }
-                    if overflow.0 == 0 {
-                        self.flag |= ZERO_FLAG;
-                    }
-                    self.reg[n] = overflow.0;
-                }
+        self.current = self.pc;
+        let ins = self.extract_u32()?;
+        let [inst1, inst2, inst3, inst4] = ins.to_be_bytes();
+        // println!("{:?}",self.reg);
+        //println!("i1 {inst1} i2 {inst2} i3 {inst3} i4 {inst4}");
+        let op_code = inst1;
+        match op_code {
+            0x00 => {}
+            0xFF => {
+                self.stop("Halt")?;
+                // stay on the halt so stepping a halted machine is a no-op
+                self.pc = self.current;
+                return Ok(Status::Halted);
+            }
+            // Mov rn,i16
+            0x01 => {
+                let reg = self.register(inst2 & 0x7F)?;
+                let value = self.immediate(inst2, inst3, inst4)?;
+                self.reg[reg] = value;
+            }
+            // add rn, rm
+            0x02 => {
+                let n = self.register(inst2)?;
+                let m = self.register(inst3)?;
+                self.reg[n] = self.add(self.reg[n], self.reg[m]);
+            }
+            // add rn, imm
+            0x03 => {
+                let n = self.register(inst2 & 0x7F)?;
+                let imm = self.immediate(inst2, inst3, inst4)?;
+                self.reg[n] = self.add(self.reg[n], imm);
+            }
+            // sub rn, rm
+            0x04 => {
+                let n = self.register(inst2)?;
+                let m = self.register(inst3)?;
+                self.reg[n] = self.sub(self.reg[n], self.reg[m]);
+            }
+            // sub rn, imm
+            0x05 => {
+                let n = self.register(inst2 & 0x7F)?;
+                let imm = self.immediate(inst2, inst3, inst4)?;
+                self.reg[n] = self.sub(self.reg[n], imm);
+            }
 
-                //cmp rn rm
-                0x06 => {
-                    self.flag &= !(ZERO_FLAG | GRETER_FLAG | LESSER_FLAG);
-
-                    let n = inst2 as usize;
-                    let m = inst3 as usize;
-
-                    let res = self.reg[n].overflowing_sub(self.reg[m]).0;
-                    // println!("{:?}",res);
-                    if res == 0 {
-                        self.flag |= ZERO_FLAG;
-                    }
-                    if res > 0 {
-                        self.flag |= GRETER_FLAG;
-                        // println!("G flag set");
-                    } else {
-                        self.flag |= LESSER_FLAG;
-                        // println!("L flag set");
-                    }
-                }
-                // JMPG
-                0x07 => {
-                    let n = u32::from_be_bytes([0x00, inst2, inst3, inst4]);
+            //cmp rn rm
+            0x06 => {
+                let n = self.register(inst2)?;
+                let m = self.register(inst3)?;
+                self.compare(self.reg[n], self.reg[m]);
+            }
+            // JMPG
+            0x07 => {
+                let target = self.branch_target(inst2, inst3, inst4);
 
-                    if (self.flag & GRETER_FLAG) != 0 {
-                        self.pc = n * 4;
-                    }
-                }
-                // JMPL
-                0x08 => {
-                    let n = u32::from_be_bytes([0x00, inst2, inst3, inst4]);
-                    if (self.flag & LESSER_FLAG) != 0 {
-                        self.pc = n * 4;
-                    }
-                }
-                // JMPGE
-                0x09 => {
-                    let n = u32::from_be_bytes([0x00, inst2, inst3, inst4]);
-                    if (self.flag & GRETER_FLAG) != 0 || (self.flag & ZERO_FLAG) != 0 {
-                        self.pc = n * 4;
-                    }
-                }
-                // JMPLE
-                0x10 => {
-                    let n = u32::from_be_bytes([0x00, inst2, inst3, inst4]);
-                    if (self.flag & LESSER_FLAG) != 0 || (self.flag & ZERO_FLAG) != 0 {
-                        self.pc = n * 4;
-                    }
-                }
-                //print reg
-                0x11 => {
-                    let n = inst2 as usize;
-                    println!("{:?}", self.reg[n]);
-                }
-                //mul rn, imm
-                0x12 => {
-                    self.flag &= !(CARRY_FLAG | ZERO_FLAG);
-                    let n = inst2 as usize;
-                    let num = i16::from_be_bytes([inst3, inst4]) as i32;
-                    let res = self.reg[n].overflowing_mul(num);
-                    self.reg[n] = res.0;
-                    if res.1 {
-                        self.flag |= CARRY_FLAG
-                    }
-                    if res.0 == 0 {
-                        self.flag |= ZERO_FLAG
-                    }
-                }
-                // Div rn ,rm
-                0x13 => {
-                    self.flag &= !ZERO_FLAG;
-                    let n = inst2 as usize;
-                    let m = inst3 as usize;
-                    if self.reg[m] == 0 {
-                        println!("Divide by zero");
-                        return;
-                    }
-                    self.reg[n] /= self.reg[m];
-                    if self.reg[n] == 0 {
-                        self.flag |= ZERO_FLAG
-                    }
-                }
-                // JMPZ
-                0x14 => {
-                    let n = u32::from_be_bytes([0x00, inst2, inst3, inst4]);
-                    if self.flag & ZERO_FLAG != 0 {
-                        self.pc = n * 4;
-                    }
+                if (self.flag & GRETER_FLAG) != 0 {
+                    self.pc = target;
                 }
-                // JMPNZ
-                0x15 => {
-                    let n = u32::from_be_bytes([0x00, inst2, inst3, inst4]);
-                    if self.flag & ZERO_FLAG == 0 {
-                        self.pc = n * 4;
-                    }
-                }
-                //JUMP
-                0x16 => {
-                    let n = u32::from_be_bytes([0x00, inst2, inst3, inst4]);
-                    self.pc = n * 4;
-                }
-                // MOV rn,rm
-                0x17 => {
-                    let n = inst2 as usize;
-                    let m = inst3 as usize;
-                    self.reg[n] = self.reg[m];
-                }
-                //cmp rn imm
-                0x18 => {
-                    self.flag &= !(ZERO_FLAG | GRETER_FLAG | LESSER_FLAG);
-
-                    let n = inst2 as usize;
-                    let val = i16::from_be_bytes([inst3, inst4]) as i32;
-                    let res = self.reg[n].overflowing_sub(val).0;
-
-                    if res == 0 {
-                        self.flag |= ZERO_FLAG;
-                    }
-                    if res > 0 {
-                        self.flag |= GRETER_FLAG;
-                    } else {
-                        self.flag |= LESSER_FLAG;
-                    }
-                }
-                //call addr
-                0x19 => {
-                    let addr = u32::from_be_bytes([0x00, inst2, inst3, inst4]);
-                    self.push(self.pc as i32);
-                    self.pc = addr * 4;
+            }
+            // JMPL
+            0x08 => {
+                let target = self.branch_target(inst2, inst3, inst4);
+                if (self.flag & LESSER_FLAG) != 0 {
+                    self.pc = target;
                 }
-                //ret
-                0x20 => {
-                    self.pc = self.pop() as u32;
+            }
+            // JMPGE
+            0x09 => {
+                let target = self.branch_target(inst2, inst3, inst4);
+                if (self.flag & GRETER_FLAG) != 0 || (self.flag & ZERO_FLAG) != 0 {
+                    self.pc = target;
                 }
-                //Push imm
-                0x21 => {
-                    let sign = if inst2 & 0x80 != 0 { 0xFF } else { 0x00 };
-                    let val = i32::from_be_bytes([sign, inst2, inst3, inst4]);
-                    self.push(val);
+            }
+            // JMPLE
+            0x10 => {
+                let target = self.branch_target(inst2, inst3, inst4);
+                if (self.flag & LESSER_FLAG) != 0 || (self.flag & ZERO_FLAG) != 0 {
+                    self.pc = target;
                 }
-                //pop
-                0x22 => {
-                    let reg = inst2 as usize;
-                    self.reg[reg] = self.pop();
+            }
+            //print reg
+            0x11 => {
+                let n = self.register(inst2)?;
+                self.output(format!("{}\n", self.reg[n]).as_bytes())?;
+            }
+            // mul rn, imm
+            0x12 => {
+                let n = self.register(inst2 & 0x7F)?;
+                let imm = self.immediate(inst2, inst3, inst4)?;
+                self.reg[n] = self.mul(self.reg[n], imm);
+            }
+            // div rn, rm
+            0x13 => {
+                let n = self.register(inst2)?;
+                let m = self.register(inst3)?;
+                let val = self.reg[m];
+                self.reg[n] = self.div(self.reg[n], val, false)?;
+            }
+            // JMPZ
+            0x14 => {
+                let target = self.branch_target(inst2, inst3, inst4);
+                if self.flag & ZERO_FLAG != 0 {
+                    self.pc = target;
                 }
-                //Push reg
-                0x23 => {
-                    let reg = inst2 as usize;
-                    self.push(self.reg[reg]);
+            }
+            // JMPNZ
+            0x15 => {
+                let target = self.branch_target(inst2, inst3, inst4);
+                if self.flag & ZERO_FLAG == 0 {
+                    self.pc = target;
                 }
-                //AND rn , rm
-                0x24 => {
-                    let n = inst2 as usize;
-                    let m = inst3 as usize;
-                    self.flag &= !ZERO_FLAG;
-                    self.reg[n] &= self.reg[m];
-                    if self.reg[n] == 0 {
-                        self.flag |= ZERO_FLAG;
-                    }
+            }
+            //JUMP
+            0x16 => {
+                let target = self.branch_target(inst2, inst3, inst4);
+                self.pc = target;
+            }
+            // MOV rn,rm
+            0x17 => {
+                let n = self.register(inst2)?;
+                let m = self.register(inst3)?;
+                self.reg[n] = self.reg[m];
+            }
+            //cmp rn imm
+            0x18 => {
+                let n = self.register(inst2 & 0x7F)?;
+                let val = self.immediate(inst2, inst3, inst4)?;
+                self.compare(self.reg[n], val);
+            }
+            //call addr
+            0x19 => {
+                let target = self.branch_target(inst2, inst3, inst4);
+                self.push(self.pc as i32)?;
+                self.call_stack.push(Frame {
+                    caller: self.current,
+                    callee: target,
+                    slot: self.sp() as u32,
+                });
+                self.pc = target;
+            }
+            //ret, `step` drops the frame
+            0x20 => {
+                self.pc = self.pop()? as u32;
+            }
+            // iret: return from a trap handler, restoring the flags
+            0x6D => {
+                // pop both before changing either, so a fault leaves them be
+                let pc = self.pop()? as u32;
+                let flag = self.pop()? as Flags;
+                self.pc = pc;
+                self.flag = flag;
+            }
+            // lea rd, code label: a 16-bit offset from this instruction
+            0x6C => {
+                let d = self.register(inst2)?;
+                let offset = i16::from_be_bytes([inst3, inst4]) as i32;
+                self.reg[d] = self.current.wrapping_add_signed(offset) as i32;
+            }
+            //Push imm
+            0x21 => {
+                let sign = if inst2 & 0x80 != 0 { 0xFF } else { 0x00 };
+                let val = i32::from_be_bytes([sign, inst2, inst3, inst4]);
+                self.push(val)?;
+            }
+            //pop
+            0x22 => {
+                let reg = self.register(inst2)?;
+                self.reg[reg] = self.pop()?;
+            }
+            //Push reg
+            0x23 => {
+                let reg = self.register(inst2)?;
+                self.push(self.reg[reg])?;
+            }
+            // and rn, rm
+            0x24 => {
+                let n = self.register(inst2)?;
+                let m = self.register(inst3)?;
+                self.reg[n] = self.logic(self.reg[n] & self.reg[m]);
+            }
+            // and rn, imm
+            0x25 => {
+                let n = self.register(inst2 & 0x7F)?;
+                let imm = self.immediate(inst2, inst3, inst4)?;
+                self.reg[n] = self.logic(self.reg[n] & imm);
+            }
+            // or rn, rm
+            0x26 => {
+                let n = self.register(inst2)?;
+                let m = self.register(inst3)?;
+                self.reg[n] = self.logic(self.reg[n] | self.reg[m]);
+            }
+            // or rn, imm
+            0x27 => {
+                let n = self.register(inst2 & 0x7F)?;
+                let imm = self.immediate(inst2, inst3, inst4)?;
+                self.reg[n] = self.logic(self.reg[n] | imm);
+            }
+            // xor rn, rm
+            0x28 => {
+                let n = self.register(inst2)?;
+                let m = self.register(inst3)?;
+                self.reg[n] = self.logic(self.reg[n] ^ self.reg[m]);
+            }
+            // xor rn, imm
+            0x29 => {
+                let n = self.register(inst2 & 0x7F)?;
+                let imm = self.immediate(inst2, inst3, inst4)?;
+                self.reg[n] = self.logic(self.reg[n] ^ imm);
+            }
+            // not rn
+            0x30 => {
+                let n = self.register(inst2)?;
+                self.reg[n] = self.logic(!self.reg[n]);
+            }
+            // mul rn, rm
+            0x31 => {
+                let n = self.register(inst2)?;
+                let m = self.register(inst3)?;
+                self.reg[n] = self.mul(self.reg[n], self.reg[m]);
+            }
+            // div rn, imm
+            0x32 => {
+                let n = self.register(inst2 & 0x7F)?;
+                let val = self.immediate(inst2, inst3, inst4)?;
+                self.reg[n] = self.div(self.reg[n], val, false)?;
+            }
+            // mod rn, rm
+            0x33 => {
+                let n = self.register(inst2)?;
+                let m = self.register(inst3)?;
+                let val = self.reg[m];
+                self.reg[n] = self.div(self.reg[n], val, true)?;
+            }
+            // mod rn, imm
+            0x34 => {
+                let n = self.register(inst2 & 0x7F)?;
+                let val = self.immediate(inst2, inst3, inst4)?;
+                self.reg[n] = self.div(self.reg[n], val, true)?;
+            }
+            //mov rn , ident // bits 4-5 of inst2 give the width
+            0x35 => {
+                let reg = self.register(inst2 & 0x0F)?;
+                let offset = self.symbol_address(inst2, inst3, inst4)?;
+                self.reg[reg] = self.read(offset, width(inst2 >> 4 & 0x03))?;
+            }
+            //cmp rn ident
+            0x36 => {
+                let offset = self.symbol_address(inst2, inst3, inst4)?;
+                let n = self.register(inst2 & 0x0F)?;
+                let val = self.read(offset, width(inst2 >> 4 & 0x03))?;
+                self.compare(self.reg[n], val);
+            }
+            // loadb/loadw/loadd rd, [rb + imm]
+            0x37..=0x39 => {
+                let d = self.register(inst2 >> 4)?;
+                let address = self.indirect_address(inst2, inst3, inst4)?;
+                self.reg[d] = self.read(address, width(op_code - 0x37))?;
+            }
+            // storeb/storew/stored rs, [rb + imm]
+            0x3A..=0x3C => {
+                let s = self.register(inst2 >> 4)?;
+                let address = self.indirect_address(inst2, inst3, inst4)?;
+                self.write(address, width(op_code - 0x3A), self.reg[s])?;
+            }
+            // loadb/loadw/loadd rd, [label]
+            0x3D..=0x3F => {
+                let d = self.register(inst2 & 0x0F)?;
+                let address = self.symbol_address(inst2, inst3, inst4)?;
+                self.reg[d] = self.read(address, width(op_code - 0x3D))?;
+            }
+            // storeb/storew/stored rs, [label]
+            0x40..=0x42 => {
+                let s = self.register(inst2 & 0x0F)?;
+                let address = self.symbol_address(inst2, inst3, inst4)?;
+                self.write(address, width(op_code - 0x40), self.reg[s])?;
+            }
+            // lea rd, [rb + imm]
+            0x43 => {
+                let d = self.register(inst2 >> 4)?;
+                let base = self.register(inst2 & 0x0F)?;
+                let offset = i16::from_be_bytes([inst3, inst4]) as i32;
+                self.reg[d] = self.reg[base].wrapping_add(offset);
+            }
+            // lea rd, [label]
+            0x44 => {
+                let d = self.register(inst2 & 0x0F)?;
+                self.reg[d] = self.symbol_address(inst2, inst3, inst4)? as i32;
+            }
+
+            // JC
+            0x45 => {
+                let target = self.branch_target(inst2, inst3, inst4);
+                if self.flag & CARRY_FLAG != 0 {
+                    self.pc = target;
                 }
-                //AND rn , imm
-                0x25 => {
-                    let n = inst2 as usize;
-                    let imm = i16::from_be_bytes([inst3, inst4]) as i32;
-                    self.flag &= !ZERO_FLAG;
-                    self.reg[n] &= imm;
-                    if self.reg[n] == 0 {
-                        self.flag |= ZERO_FLAG;
-                    }
+            }
+            // JNC
+            0x46 => {
+                let target = self.branch_target(inst2, inst3, inst4);
+                if self.flag & CARRY_FLAG == 0 {
+                    self.pc = target;
                 }
-                //OR rn , rm
-                0x26 => {
-                    let n = inst2 as usize;
-                    let m = inst3 as usize;
-                    self.flag &= !ZERO_FLAG;
-                    self.reg[n] |= self.reg[m];
-                    if self.reg[n] == 0 {
-                        self.flag |= ZERO_FLAG;
-                    }
+            }
+            // JO
+            0x47 => {
+                let target = self.branch_target(inst2, inst3, inst4);
+                if self.flag & OVERFLOW_FLAG != 0 {
+                    self.pc = target;
                 }
-                //OR rn , imm
-                0x27 => {
-                    let n = inst2 as usize;
-                    let imm = i16::from_be_bytes([inst3, inst4]) as i32;
-                    self.flag &= !ZERO_FLAG;
-                    self.reg[n] |= imm;
-                    if self.reg[n] == 0 {
-                        self.flag |= ZERO_FLAG;
-                    }
+            }
+            // JA, unsigned above
+            0x48 => {
+                let target = self.branch_target(inst2, inst3, inst4);
+                if self.flag & (CARRY_FLAG | ZERO_FLAG) == 0 {
+                    self.pc = target;
                 }
-                //XOR rn , rm
-                0x28 => {
-                    let n = inst2 as usize;
-                    let m = inst3 as usize;
-                    self.flag &= !ZERO_FLAG;
-                    self.reg[n] ^= self.reg[m];
-                    if self.reg[n] == 0 {
-                        self.flag |= ZERO_FLAG;
-                    }
+            }
+            // JB, unsigned below
+            0x49 => {
+                let target = self.branch_target(inst2, inst3, inst4);
+                if self.flag & CARRY_FLAG != 0 {
+                    self.pc = target;
                 }
-                //XOR rn , imm
-                0x29 => {
-                    let n = inst2 as usize;
-                    let imm = i16::from_be_bytes([inst3, inst4]) as i32;
-                    self.flag &= !ZERO_FLAG;
-                    self.reg[n] ^= imm;
-                    if self.reg[n] == 0 {
-                        self.flag |= ZERO_FLAG;
-                    }
+            }
+            // JAE, unsigned above or equal
+            0x4A => {
+                let target = self.branch_target(inst2, inst3, inst4);
+                if self.flag & CARRY_FLAG == 0 {
+                    self.pc = target;
                 }
-                //NOT rn
-                0x30 => {
-                    let n = inst2 as usize;
-                    self.flag &= !ZERO_FLAG;
-                    self.reg[n] = !self.reg[n];
-                    if self.reg[n] == 0 {
-                        self.flag |= ZERO_FLAG;
-                    }
+            }
+            // JBE, unsigned below or equal
+            0x4B => {
+                let target = self.branch_target(inst2, inst3, inst4);
+                if self.flag & (CARRY_FLAG | ZERO_FLAG) != 0 {
+                    self.pc = target;
                 }
-                //mul rn, imm 0x12
-                //mul rn,rm
-                0x31 => {
-                    self.flag &= !(CARRY_FLAG | ZERO_FLAG);
-                    let n = inst2 as usize;
-                    let m = inst3 as usize;
-                    let res = self.reg[n].overflowing_mul(self.reg[m]);
-                    self.reg[n] = res.0;
-                    if res.1 {
-                        self.flag |= CARRY_FLAG
-                    }
-                    if res.0 == 0 {
-                        self.flag |= ZERO_FLAG
-                    }
+            }
+            // JE
+            0x4C => {
+                let target = self.branch_target(inst2, inst3, inst4);
+                if self.flag & ZERO_FLAG != 0 {
+                    self.pc = target;
                 }
-                // Div rn ,rm 0x13
-                // Div rn,imm
-                0x32 => {
-                    self.flag &= !ZERO_FLAG;
-                    let n = inst2 as usize;
-                    let val = i16::from_be_bytes([inst3, inst4]) as i32;
-                    if val == 0 {
-                        println!("Divide by zero");
-                        return;
-                    }
-                    self.reg[n] /= val;
-                    if self.reg[n] == 0 {
-                        self.flag |= ZERO_FLAG
-                    }
+            }
+            // JNE
+            0x4D => {
+                let target = self.branch_target(inst2, inst3, inst4);
+                if self.flag & ZERO_FLAG == 0 {
+                    self.pc = target;
                 }
-                //mod rn,rm
-                0x33 => {
-                    self.flag &= !(CARRY_FLAG | ZERO_FLAG);
-                    let n = inst2 as usize;
-                    let m = inst3 as usize;
-                    let res = self.reg[n] % (self.reg[m]);
-                    self.reg[n] = res;
-                    if res == 0 {
-                        self.flag |= ZERO_FLAG
-                    }
+            }
+            //Push imm32, the value is the next word
+            0x4E => {
+                let val = self.extract_u32()? as i32;
+                self.push(val)?;
+            }
+            // shl/shr/sar/rol/ror rn, rm (odd) or rn, imm (even)
+            0x4F..=0x58 => {
+                let n = self.register(inst2 & 0x7F)?;
+                let count = if (op_code - 0x4F) % 2 == 0 {
+                    self.reg[self.register(inst3)?]
+                } else {
+                    self.immediate(inst2, inst3, inst4)?
+                };
+                self.reg[n] = self.shift((op_code - 0x4F) / 2, self.reg[n], count);
+            }
+            // inc rn / dec rn, flags as for add/sub with 1
+            0x59 | 0x5A => {
+                let n = self.register(inst2)?;
+                self.reg[n] = if op_code == 0x59 {
+                    self.add(self.reg[n], 1)
+                } else {
+                    self.sub(self.reg[n], 1)
+                };
+            }
+            // neg rn, flags as for 0 - rn
+            0x5B => {
+                let n = self.register(inst2)?;
+                self.reg[n] = self.sub(0, self.reg[n]);
+            }
+            // test rn, rm / test rn, imm: and without writing back
+            0x5C | 0x5D => {
+                let n = self.register(inst2 & 0x7F)?;
+                let val = if op_code == 0x5C {
+                    self.reg[self.register(inst3)?]
+                } else {
+                    self.immediate(inst2, inst3, inst4)?
+                };
+                self.logic(self.reg[n] & val);
+            }
+            // pushf
+            0x5E => {
+                self.push(self.flag as i32)?;
+            }
+            // read rn, a line parsed as a number, 0 if it is not one
+            0x64 => {
+                let n = self.register(inst2)?;
+                let line = self.input_line()?.unwrap_or_default();
+                self.reg[n] = String::from_utf8_lossy(&line).trim().parse().unwrap_or(0);
+            }
+            // readc rn, -1 at the end of input
+            0x65 => {
+                let n = self.register(inst2)?;
+                self.reg[n] = self.input_byte()?.map_or(-1, |byte| byte as i32);
+            }
+            // readln rd, [rb + imm] / [label]: rd holds the buffer size and
+            // receives the line length, -1 at the end of input
+            0x66 | 0x67 => {
+                let (d, address) = if op_code == 0x66 {
+                    (inst2 >> 4, self.indirect_address(inst2, inst3, inst4)?)
+                } else {
+                    (inst2 & 0x0F, self.symbol_address(inst2, inst3, inst4)?)
+                };
+                let d = self.register(d)?;
+                let capacity = self.reg[d].max(0) as usize;
+                self.reg[d] = self
+                    .input_line_into(address, capacity)?
+                    .map_or(-1, |len| len as i32);
+            }
+            // printc rn, the low byte
+            0x69 => {
+                let n = self.register(inst2)?;
+                self.output(&[self.reg[n] as u8])?;
+            }
+            // prints [rb + imm] / [label], a null-terminated string
+            0x6A | 0x6B => {
+                let address = if op_code == 0x6A {
+                    self.indirect_address(inst2, inst3, inst4)?
+                } else {
+                    self.symbol_address(inst2, inst3, inst4)?
+                };
+                let text = self.string_at(address)?;
+                self.output(&text)?;
+            }
+            // alloc rn, rm / alloc rn, imm: rn = a zeroed heap block
+            0x6E | 0x6F => {
+                let n = self.register(inst2 & 0x7F)?;
+                let size = if op_code == 0x6E {
+                    self.reg[self.register(inst3)?]
+                } else {
+                    self.immediate(inst2, inst3, inst4)?
+                };
+                let pc = self.current;
+                let len = usize::try_from(size).map_err(|_| VmError::OutOfMemory { pc, size })?;
+                let address = self
+                    .heap
+                    .alloc(len)
+                    .ok_or(VmError::OutOfMemory { pc, size })?;
+                self.memory[address..address + len].fill(0);
+                self.reg[n] = address as i32;
+            }
+            // free rn, freeing 0 does nothing
+            0x70 => {
+                let n = self.register(inst2)?;
+                let address = self.reg[n] as u32 as usize;
+                if address != 0 {
+                    let pc = self.current;
+                    self.heap.free(address).map_err(|err| match err {
+                        FreeError::DoubleFree => VmError::DoubleFree { pc, address },
+                        FreeError::NotABlock => VmError::InvalidFree { pc, address },
+                    })?;
                 }
-                //mod rn,imm
-                0x34 => {
-                    self.flag &= !ZERO_FLAG;
-                    let n = inst2 as usize;
-                    let val = i16::from_be_bytes([inst3, inst4]) as i32;
-                    self.reg[n] %= val;
-                    if self.reg[n] == 0 {
-                        self.flag |= ZERO_FLAG
-                    }
+            }
+            // JEOF
+            0x68 => {
+                let target = self.branch_target(inst2, inst3, inst4);
+                if self.flag & EOF_FLAG != 0 {
+                    self.pc = target;
                 }
-                //mov rn , [addr] // value of u8
-                0x35 => {
-                    let reg = inst2 as usize;
-                    let offset = DATA_START + u16::from_be_bytes([inst3, inst4]) as usize;
-                    assert!(
-                        (DATA_START..BSS_START).contains(&offset),
-                        "seg fault {:?}",
-                        offset
-                    );
-                    self.reg[reg] = self.memory[offset] as i8 as i16 as i32;
-                },
-                 //cmp rn addr // value u8
-                0x36 => {
-                    self.flag &= !(ZERO_FLAG | GRETER_FLAG | LESSER_FLAG);
-                    let offset = DATA_START + u16::from_be_bytes([inst3, inst4]) as usize;
-                    assert!(
-                    (DATA_START..BSS_START).contains(&offset),
-                        "seg fault {:?}",
-                        offset
-                    );
-                    let n = inst2 as usize;
-                    let val = self.memory[offset] as i8 as i16 as i32;
-                    let res = self.reg[n].overflowing_sub(val).0;
-
-                    if res == 0 {
-                        self.flag |= ZERO_FLAG;
-                    }
-                    if res > 0 {
-                        self.flag |= GRETER_FLAG;
-                    } else {
-                        self.flag |= LESSER_FLAG;
-                    }
+            }
+            // popf
+            0x5F => {
+                self.flag = self.pop()? as Flags;
+            }
+            // enter n: push fp, fp = sp, reserve n bytes of locals
+            0x60 => {
+                let size = u16::from_be_bytes([inst3, inst4]) as usize;
+                // check the whole frame first so a fault leaves sp and fp be
+                if self.sp() < self.config.stack_end() + 4 + size {
+                    return Err(VmError::StackOverflow { pc: self.current });
                 }
+                self.push(self.reg[FP])?;
+                self.reg[FP] = self.reg[SP];
+                self.reg[SP] -= size as i32;
+            }
+            // leave: sp = fp, pop fp
+            0x61 => {
+                let fp = self.reg[FP] as u32 as usize;
+                let saved = self.stack_slot(fp)?;
+                self.reg[SP] = (fp + 4) as i32;
+                self.reg[FP] = saved;
+            }
+            // int n
+            0x62 => return self.interrupt(inst2 as i32),
+            // syscall, the number is in r0
+            0x63 => return self.interrupt(self.reg[0]),
+
+            _ => {
+                return Err(VmError::InvalidOpcode {
+                    pc: self.current,
+                    opcode: op_code,
+                });
+            }
+        }
+        Ok(Status::Running)
+    }
 
-                _ => todo!(),
+    /// Runs the host handler for `number`; only 0..=255 can have one. The
+    /// handler is cloned out first, so it can borrow the VM mutably.
+    fn interrupt(&mut self, number: i32) -> Result<Status, VmError> {
+        let handler = u8::try_from(number)
+            .ok()
+            .and_then(|number| self.syscalls.get(&number))
+            .cloned()
+            .ok_or(VmError::UnknownSyscall {
+                pc: self.current,
+                number,
+            })?;
+        handler(self)?;
+        match self.exit_code.take() {
+            Some(code) => {
+                self.stop(&format!("Exit {code}"))?;
+                // like halt, stay on the instruction that stopped the program
+                self.pc = self.current;
+                Ok(Status::Exited(code))
             }
+            None => Ok(Status::Running),
         }
     }
 
-    pub fn copy(&mut self, program: &[u8], data: &[u8]) {
-        let start = CODE_START;
-        let end = program.len() + start;
-        assert!(end < DATA_START, "program is too large");
-        self.memory[start..program.len()].copy_from_slice(program);
+    /// Loads an assembled program: code at `CODE_START`, data and a zeroed
+    /// bss at the configured segments, and points the pc at the entry point.
+    /// Registers, flags, the call stack and the heap start afresh, so a VM
+    /// can run one program after another.
+    pub fn load(&mut self, object: &Object) -> Result<(), LoadError> {
+        let VmConfig {
+            data_start,
+            bss_start,
+            heap_start,
+            ..
+        } = self.config;
+        let vector_table = self.config.vector_table();
+        let sections = [
+            (CODE_START, data_start, object.code.len()),
+            (data_start, bss_start, object.data.len()),
+            (bss_start, vector_table, object.bss_size as usize),
+        ];
+        for (start, limit, size) in sections {
+            if start + size > limit {
+                return Err(LoadError::ProgramTooLarge {
+                    size,
+                    limit: limit - start,
+                });
+            }
+        }
+        if object.entry != 0 && object.entry as usize >= object.code.len() {
+            return Err(LoadError::InvalidEntry {
+                entry: object.entry,
+            });
+        }
 
-        let start = DATA_START;
-        let end = start + data.len();
-        assert!(end <= self.memory.len(), "data too big for memory");
-        assert!(end < BSS_START, "seg fault");
-        self.memory[start..end].copy_from_slice(data);
+        self.memory[CODE_START..CODE_START + object.code.len()].copy_from_slice(&object.code);
+        self.memory[data_start..data_start + object.data.len()].copy_from_slice(&object.data);
+        self.memory[bss_start..bss_start + object.bss_size as usize].fill(0);
+        self.memory[vector_table..heap_start].fill(0);
+        self.pc = CODE_START as u32 + object.entry;
+        self.current = self.pc;
+        self.flag = 0;
+        self.reg = stack_registers(&self.config);
+        self.call_stack.clear();
+        self.exit_code = None;
+        self.heap = Heap::new(heap_start, self.config.heap_end());
+        Ok(())
     }
 }
+
+/// Registers at reset: zero, with `sp` and `fp` at the top of the stack.
+fn stack_registers(config: &VmConfig) -> [i32; REGISTERS] {
+    let mut reg = [0; REGISTERS];
+    reg[SP] = config.stack_start() as i32;
+    reg[FP] = config.stack_start() as i32;
+    reg
+}
+
+/// Access width in bytes for a width code, 0 byte, 1 word, 2 dword. Also
+/// the position of an opcode in its byte/word/dword group.
+fn width(n: u8) -> usize {
+    1 << n.min(2)
+}